@@ -0,0 +1,228 @@
+// Contract artifacts
+//
+// A contract is compiled one function at a time, so that each of its entry points carries its own
+// bytecode. The artifact follows the shape of the contract artifacts Noir writes and Aztec tooling
+// deploys: every function is public, so it is unconstrained bytecode run by the AVM, with an ABI
+// describing the parameters declared by its `.entry` directive.
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Serialize, Serializer};
+use sha3::{Digest, Keccak256};
+
+/// A compiled contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractArtifact {
+    pub name: String,
+    pub functions: Vec<ContractFunction>,
+}
+
+/// A public function of a contract, along with its bytecode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContractFunction {
+    pub name: String,
+    /// Public functions are run by the AVM rather than proven, so are always unconstrained
+    pub is_unconstrained: bool,
+    pub custom_attributes: Vec<String>,
+    pub abi: FunctionAbi,
+    /// Written out as base64
+    #[serde(serialize_with = "serialize_bytecode")]
+    pub bytecode: Vec<u8>,
+    #[serde(serialize_with = "serialize_selector")]
+    pub selector: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionAbi {
+    pub parameters: Vec<AbiParameter>,
+    pub return_type: Option<AbiType>,
+    pub error_types: BTreeMap<String, AbiType>,
+}
+
+/// A parameter of a function. Signatures only give types, so parameters are named by position.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AbiParameter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: AbiType,
+    pub visibility: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum AbiType {
+    Field,
+    Boolean,
+    Integer { sign: Sign, width: u32 },
+    Tuple { fields: Vec<AbiType> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sign {
+    Unsigned,
+    Signed,
+}
+
+impl ContractArtifact {
+    pub fn to_json(&self) -> String {
+        // Fields that hand written bytecode has nothing to put in are left empty
+        let artifact = serde_json::json!({
+            "noir_version": concat!("avm-asm ", env!("CARGO_PKG_VERSION")),
+            "name": self.name,
+            "functions": self.functions,
+            "outputs": { "structs": {}, "globals": {} },
+            "file_map": {},
+            "transpiled": true,
+        });
+        serde_json::to_string_pretty(&artifact).expect("contract artifacts are always serializable")
+    }
+}
+
+impl ContractFunction {
+    /// A function selected by its signature, e.g. `transfer(Field,u32)`, failing if the signature
+    /// has a type the ABI cannot describe.
+    pub fn new(name: String, signature: &str, bytecode: Vec<u8>) -> Result<Self, String> {
+        let parameters = signature_types(signature)
+            .iter()
+            .enumerate()
+            .map(|(index, ty)| {
+                Ok(AbiParameter {
+                    name: format!("arg{index}"),
+                    ty: abi_type(ty)?,
+                    visibility: "private".to_owned(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(ContractFunction {
+            selector: selector(signature),
+            name,
+            is_unconstrained: true,
+            custom_attributes: vec!["public".to_owned()],
+            abi: FunctionAbi {
+                parameters,
+                return_type: None,
+                error_types: BTreeMap::new(),
+            },
+            bytecode,
+        })
+    }
+}
+
+/// The selector of a function signature such as `transfer(Field,Field)`, the first four bytes of
+/// its keccak256 hash.
+pub fn selector(signature: &str) -> u32 {
+    let hash = Keccak256::digest(signature.as_bytes());
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+// The types between the outermost parentheses of a signature, split at the commas that are not
+// within a tuple
+fn signature_types(signature: &str) -> Vec<&str> {
+    let inner = match (signature.find('('), signature.rfind(')')) {
+        (Some(start), Some(end)) if start < end => &signature[start + 1..end],
+        _ => return Vec::new(),
+    };
+    split_types(inner)
+}
+
+fn split_types(list: &str) -> Vec<&str> {
+    if list.is_empty() {
+        return Vec::new();
+    }
+    let mut types = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (index, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(&list[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    types.push(&list[start..]);
+    types
+}
+
+fn abi_type(ty: &str) -> Result<AbiType, String> {
+    if let Some(fields) = ty.strip_prefix('(').and_then(|ty| ty.strip_suffix(')')) {
+        let fields = split_types(fields)
+            .into_iter()
+            .map(abi_type)
+            .collect::<Result<_, _>>()?;
+        return Ok(AbiType::Tuple { fields });
+    }
+
+    let integer = |sign, width: &str| match width.parse() {
+        Ok(width @ (1 | 8 | 16 | 32 | 64 | 128)) => Ok(AbiType::Integer { sign, width }),
+        _ => Err(format!("type `{ty}` cannot be described in a contract ABI")),
+    };
+    match ty {
+        "Field" | "ff" => Ok(AbiType::Field),
+        "bool" => Ok(AbiType::Boolean),
+        _ if ty.starts_with('u') => integer(Sign::Unsigned, &ty[1..]),
+        _ if ty.starts_with('i') => integer(Sign::Signed, &ty[1..]),
+        _ => Err(format!("type `{ty}` cannot be described in a contract ABI")),
+    }
+}
+
+fn serialize_selector<S: Serializer>(selector: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{selector:08x}"))
+}
+
+fn serialize_bytecode<S: Serializer>(bytecode: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bytecode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_hash_the_signature() {
+        assert_eq!(selector("transfer(address,uint256)"), 0xa9059cbb);
+        assert_eq!(
+            ContractFunction::new("mint".to_owned(), "mint()", vec![])
+                .unwrap()
+                .selector,
+            selector("mint()")
+        );
+    }
+
+    #[test]
+    fn signatures_become_abi_parameters() {
+        let function =
+            ContractFunction::new("f".to_owned(), "f(Field,u32,(bool,i8))", vec![]).unwrap();
+        let types: Vec<_> = function.abi.parameters.iter().map(|p| &p.ty).collect();
+        assert_eq!(
+            types,
+            [
+                &AbiType::Field,
+                &AbiType::Integer {
+                    sign: Sign::Unsigned,
+                    width: 32
+                },
+                &AbiType::Tuple {
+                    fields: vec![
+                        AbiType::Boolean,
+                        AbiType::Integer {
+                            sign: Sign::Signed,
+                            width: 8
+                        }
+                    ]
+                },
+            ]
+        );
+        assert_eq!(function.abi.parameters[2].name, "arg2");
+
+        assert_eq!(
+            ContractFunction::new("f".to_owned(), "f(Point)", vec![]),
+            Err("type `Point` cannot be described in a contract ABI".to_owned())
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// A builder for assembling programs from Rust
+//
+// `Assembler` gathers everything that configures compilation, and returns the compiled program
+// along with its instructions, labels, source map and any warnings, rather than just the bytecode.
+
+use std::{collections::BTreeMap, fmt, path::PathBuf, rc::Rc};
+
+use num_bigint::BigUint;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::loader::FsLoader;
+#[cfg(target_arch = "wasm32")]
+use crate::loader::MemoryLoader;
+use crate::{
+    compiler::{assemble_file, assemble_source, Assembly, CompileOptions, OutputFormat, INPUT},
+    diagnostic::Diagnostic,
+    instruction::Instruction,
+    isa::Isa,
+    loader::FileLoader,
+    source_map::{DebugInfo, Sources},
+};
+
+/// Assembles programs with the given settings.
+///
+/// ```
+/// use avm_asm::{assembler::Assembler, isa::Isa};
+///
+/// let program = Assembler::new()
+///     .isa(Isa::V1)
+///     .define("slot", 3u32)
+///     .assemble("sload $slot 0;")
+///     .unwrap();
+/// assert_eq!(program.instructions.len(), 1);
+/// ```
+#[derive(Clone)]
+pub struct Assembler {
+    options: CompileOptions,
+    format: OutputFormat,
+    loader: Rc<dyn FileLoader>,
+}
+
+/// A compiled program, along with what it was compiled from.
+#[derive(Debug, Clone)]
+pub struct CompiledProgram {
+    /// The bytecode, written out in the assembler's output format
+    pub bytes: Vec<u8>,
+    pub instructions: Vec<Instruction>,
+    /// The index of the instruction each label refers to. Labels local to a macro are suffixed
+    /// with `#` and the number of the expansion they belong to, e.g. `%loop#3`.
+    pub labels: BTreeMap<String, u64>,
+    pub source_map: DebugInfo,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler {
+            options: CompileOptions::default(),
+            format: OutputFormat::default(),
+            // There are no files to read in the browser unless they are given through `loader`
+            #[cfg(not(target_arch = "wasm32"))]
+            loader: Rc::new(FsLoader),
+            #[cfg(target_arch = "wasm32")]
+            loader: Rc::new(MemoryLoader::new()),
+        }
+    }
+}
+
+impl fmt::Debug for Assembler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Assembler")
+            .field("options", &self.options)
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler::default()
+    }
+
+    /// Read files through the given loader rather than from disk, e.g. a `MemoryLoader`.
+    pub fn loader(mut self, loader: impl FileLoader + 'static) -> Self {
+        self.loader = Rc::new(loader);
+        self
+    }
+
+    /// Search this directory for included files, after the directory of the including file and
+    /// any include paths added before it.
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.include_paths.push(path.into());
+        self
+    }
+
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.options.isa = isa;
+        self
+    }
+
+    /// Define a constant, as though with `.const`.
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<BigUint>) -> Self {
+        self.options.defines.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn max_expansion_depth(mut self, depth: usize) -> Self {
+        self.options.max_expansion_depth = depth;
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Assemble the file at `path`, along with everything it includes.
+    pub fn assemble_file(
+        &self,
+        path: impl Into<String>,
+    ) -> Result<CompiledProgram, Vec<Diagnostic>> {
+        let assembly = assemble_file(&path.into(), &self.options, &*self.loader)?;
+        Ok(self.finish(assembly, Sources::new(self.loader.clone())))
+    }
+
+    /// Assemble source that is not read from a file, which diagnostics refer to as `<input>`.
+    /// Files it includes are looked up relative to the current directory.
+    pub fn assemble(&self, source: &str) -> Result<CompiledProgram, Vec<Diagnostic>> {
+        let assembly = assemble_source(source, &self.options, &*self.loader)?;
+        let mut sources = Sources::new(self.loader.clone());
+        sources.insert(INPUT, source);
+        Ok(self.finish(assembly, sources))
+    }
+
+    fn finish(&self, assembly: Assembly, mut sources: Sources) -> CompiledProgram {
+        let source_map = DebugInfo::new(&assembly, &mut sources);
+        CompiledProgram {
+            bytes: self.format.render(&assembly.bytecode),
+            labels: source_map.labels.clone(),
+            source_map,
+            instructions: assembly.instructions,
+            warnings: assembly.warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loader::MemoryLoader, Opcode, Operand};
+
+    #[test]
+    fn programs_are_assembled_with_their_settings() {
+        let program = Assembler::new()
+            .define("value", 0x2au32)
+            .format(OutputFormat::PrefixedHex)
+            .assemble("main:\n    set u8 $value 0;\n    return 0 1;")
+            .unwrap();
+
+        assert_eq!(program.instructions[0].opcode, Opcode::SET);
+        assert_eq!(
+            program.instructions[0].operands[1],
+            Operand::Hex("0x2a".to_owned())
+        );
+        assert_eq!(program.labels, BTreeMap::from([("main".to_owned(), 0)]));
+        assert!(program.bytes.starts_with(b"0x"));
+        assert_eq!(program.source_map.instructions[1].location.line, 3);
+        assert!(program.warnings.is_empty());
+    }
+
+    #[test]
+    fn includes_can_be_served_from_memory() {
+        let loader = MemoryLoader::new()
+            .with_file(
+                "main.avm",
+                ".include \"lib/math.avm\" as math;\n$math::double(1);",
+            )
+            .with_file(
+                "lib/math.avm",
+                ".include \"../consts.avm\";\n.macro double(a) {\n    add $a $a $two;\n};",
+            )
+            .with_file("consts.avm", ".const two = 2;");
+        let assembler = Assembler::new().loader(loader);
+
+        let program = assembler.assemble_file("main.avm").unwrap();
+        assert_eq!(
+            program.instructions,
+            [Instruction::new(
+                Opcode::ADD,
+                false,
+                vec![1.into(), 1.into(), 2.into()]
+            )]
+        );
+        let location = &program.source_map.instructions[0].location;
+        assert_eq!((location.file.as_str(), location.line), ("lib/math.avm", 3));
+
+        let program = assembler
+            .assemble(".include \"lib/math.avm\";\n$double(3);")
+            .unwrap();
+        assert_eq!(program.instructions[0].operands[0], 3.into());
+
+        let errors = assembler.assemble(".include \"missing.avm\";").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "could not find included file `missing.avm`"
+        );
+    }
+
+    #[test]
+    fn defines_cannot_be_redefined() {
+        let errors = Assembler::new()
+            .define("value", 1u32)
+            .assemble(".const value = 2;")
+            .unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "constant `$value` is defined multiple times"
+        );
+    }
+}
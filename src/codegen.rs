@@ -1,13 +1,152 @@
-use crate::{instruction::Instruction, utils::bytes_to_hex_string};
+use std::{fmt, str::FromStr};
 
-// Generate code from a string of instructions
-pub fn generate_code(instructions: Vec<Instruction>) -> String {
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{instruction::Instruction, isa::Isa, utils::bytes_to_hex_string};
+
+/// How bytecode is written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Uppercase hex
+    #[default]
+    Hex,
+    /// Lowercase hex
+    LowercaseHex,
+    /// Lowercase hex with a `0x` prefix
+    PrefixedHex,
+    /// Standard base64, as contract artifacts embed bytecode
+    Base64,
+    /// The raw bytes
+    Binary,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [
+        OutputFormat::Hex,
+        OutputFormat::LowercaseHex,
+        OutputFormat::PrefixedHex,
+        OutputFormat::Base64,
+        OutputFormat::Binary,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Hex => "hex",
+            OutputFormat::LowercaseHex => "lowercase-hex",
+            OutputFormat::PrefixedHex => "prefixed-hex",
+            OutputFormat::Base64 => "base64",
+            OutputFormat::Binary => "binary",
+        }
+    }
+
+    /// Whether the output is text, rather than raw bytes.
+    pub fn is_text(&self) -> bool {
+        *self != OutputFormat::Binary
+    }
+
+    /// Write out encoded bytecode in this format. Text formats are returned as UTF-8.
+    pub fn render(&self, bytecode: &[u8]) -> Vec<u8> {
+        match self {
+            OutputFormat::Hex => bytes_to_hex_string(bytecode).into_bytes(),
+            OutputFormat::LowercaseHex => hex::encode(bytecode).into_bytes(),
+            OutputFormat::PrefixedHex => format!("0x{}", hex::encode(bytecode)).into_bytes(),
+            OutputFormat::Base64 => STANDARD.encode(bytecode).into_bytes(),
+            OutputFormat::Binary => bytecode.to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| {
+                let available: Vec<&str> =
+                    OutputFormat::ALL.iter().map(OutputFormat::name).collect();
+                format!(
+                    "unknown output format `{name}`, expected one of {}",
+                    available.join(", ")
+                )
+            })
+    }
+}
+
+/// An instruction that could not be encoded, identified by its position in the program
+#[derive(Debug)]
+pub struct CodegenError {
+    pub index: usize,
+    pub operand: Option<usize>,
+    pub message: String,
+}
+
+// Generate code from a string of instructions, encoded for the given instruction set. Tests compare
+// programs against hand built instructions encoded this way
+#[cfg(test)]
+pub fn generate_code(
+    instructions: Vec<Instruction>,
+    isa: Isa,
+) -> Result<String, Vec<CodegenError>> {
+    encode(&instructions, isa).map(|(bytecode, _)| bytes_to_hex_string(&bytecode))
+}
+
+// Encode each instruction, returning the bytecode along with the byte offset of every instruction
+pub(crate) fn encode(
+    instructions: &[Instruction],
+    isa: Isa,
+) -> Result<(Vec<u8>, Vec<usize>), Vec<CodegenError>> {
     let mut bytecode = Vec::new();
+    let mut offsets = Vec::new();
+    let mut errors = Vec::new();
 
     // TODO: make sure these are converted to hex bytes accurately
-    for instr in instructions {
-        instr.append_to_buffer(&mut bytecode);
+    for (index, instr) in instructions.iter().enumerate() {
+        offsets.push(bytecode.len());
+        if let Err(error) = instr.append_to_buffer(isa, &mut bytecode) {
+            errors.push(CodegenError {
+                index,
+                operand: error.operand,
+                message: error.message,
+            });
+        }
     }
 
-    bytes_to_hex_string(&bytecode)
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok((bytecode, offsets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_formats() {
+        let bytecode = [0x00, 0xab, 0xff];
+        let render = |format: OutputFormat| format.render(&bytecode);
+
+        assert_eq!(render(OutputFormat::Hex), b"00ABFF");
+        assert_eq!(render(OutputFormat::LowercaseHex), b"00abff");
+        assert_eq!(render(OutputFormat::PrefixedHex), b"0x00abff");
+        assert_eq!(render(OutputFormat::Base64), b"AKv/");
+        assert_eq!(render(OutputFormat::Binary), bytecode);
+
+        for format in OutputFormat::ALL {
+            assert_eq!(format.name().parse(), Ok(format));
+        }
+        assert_eq!(
+            "hex0x".parse::<OutputFormat>(),
+            Err("unknown output format `hex0x`, expected one of hex, lowercase-hex, prefixed-hex, base64, binary".to_owned())
+        );
+    }
 }
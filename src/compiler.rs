@@ -1,87 +1,648 @@
 // Compiler
 // Read in the AST from the parser
 
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use num_bigint::BigUint;
 
+pub use crate::codegen::OutputFormat;
+use crate::{
+    artifact::{selector, ContractArtifact, ContractFunction},
+    codegen::encode,
+    diagnostic::Diagnostic,
+    fm::{FileManager, SourcePath},
+    instruction::Instruction,
+    isa::{Isa, OperandKind},
+    loader::FileLoader,
+    opcodes::Opcode,
+    parser::{
+        parse_asm, MacroArgument, MacroParameter, Operand, ParameterKind, Statement, TypeTag,
+    },
+    span::{Span, Spanned},
+    utils::bytes_to_hex_string,
+};
+#[cfg(not(target_arch = "wasm32"))]
 use crate::{
-    codegen::generate_code, fm::FileManager, instruction::Instruction, opcodes::Opcode, parser::{parse_asm, Operand, Statement}
+    loader::FsLoader,
+    source_map::{DebugInfo, Sources},
 };
 
-pub fn compile_file(path: &String) -> String {
-    let file = std::fs::read_to_string(path).unwrap();
-    
-    // TODO: make the file manager keep track of the parent when dealing with include statements
-    // Maybe keep them in a tuple? Or deal with them when extending
-    let mut fm = FileManager::new();
+/// Settings that control compilation
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// How deeply macros may invoke other macros before compilation is aborted
+    pub max_expansion_depth: usize,
+    /// Directories searched for included files, after the directory of the including file
+    pub include_paths: Vec<PathBuf>,
+    /// The instruction set revision to assemble for
+    pub isa: Isa,
+    /// Constants defined outside of the program, as though with `.const`
+    pub defines: BTreeMap<String, BigUint>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            max_expansion_depth: 64,
+            include_paths: Vec::new(),
+            isa: Isa::default(),
+            defines: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn compile_file(path: &String) -> Result<String, Vec<Diagnostic>> {
+    compile_file_with_options(path, &CompileOptions::default())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn compile_file_with_options(
+    path: &String,
+    options: &CompileOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    assemble_file(path, options, &FsLoader).map(|assembly| bytes_to_hex_string(&assembly.bytecode))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Compile a file, writing the bytecode out in the given format
+pub fn compile_file_as(
+    path: &String,
+    options: &CompileOptions,
+    format: OutputFormat,
+) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    assemble_file(path, options, &FsLoader).map(|assembly| format.render(&assembly.bytecode))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Compile a file in the given format, along with debug info mapping each instruction back to
+/// where it was written
+pub fn compile_file_with_debug_info(
+    path: &String,
+    options: &CompileOptions,
+    format: OutputFormat,
+) -> Result<(Vec<u8>, DebugInfo), Vec<Diagnostic>> {
+    let assembly = assemble_file(path, options, &FsLoader)?;
+    let debug_info = DebugInfo::new(&assembly, &mut Sources::default());
+    Ok((format.render(&assembly.bytecode), debug_info))
+}
+
+// Compile a file along with everything it includes, keeping what is needed to map the program
+// back to its source
+pub(crate) fn assemble_file(
+    path: &String,
+    options: &CompileOptions,
+    loader: &dyn FileLoader,
+) -> Result<Assembly, Vec<Diagnostic>> {
+    let parsed = load_file(path, options, loader)?;
+    assemble(parsed, options)
+}
 
-    let mut parsed = parse_asm(file);
-    fm.extend_file_stack(&parsed);
+/// Compile a file as a contract, each of its entry points becoming a separately compiled function.
+///
+/// Entry points are the labels written at the top level of the file that are never jumped to,
+/// each function running from its entry point up to the next one. Functions are selected by the
+/// signature given in their `.entry` directive, or by `name()` if they have none.
+pub fn compile_contract(
+    path: &String,
+    options: &CompileOptions,
+    loader: &dyn FileLoader,
+) -> Result<ContractArtifact, Vec<Diagnostic>> {
+    let parsed = load_file(path, options, loader)?;
+
+    let mut diagnostics = Vec::new();
+    let mut parsed = expand(parsed, options, &mut diagnostics);
+    let entry_points = take_entry_points(&mut parsed, &mut diagnostics);
+    let functions = split_functions(parsed, path, &mut diagnostics);
+    for entry_point in &entry_points {
+        if !functions.iter().any(|(name, _)| *name == entry_point.label) {
+            diagnostics.push(
+                Diagnostic::error_at(
+                    &entry_point.span,
+                    format!(
+                        "`{}` is not an entry point of the contract",
+                        entry_point.label
+                    ),
+                )
+                .with_note(
+                    "entry points are labels at the top level of the file that are never jumped to",
+                ),
+            );
+        }
+    }
+    if functions.is_empty() && diagnostics.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            path,
+            0..0,
+            format!("`{path}` has no entry points"),
+        ));
+    }
+
+    let mut compiled = Vec::new();
+    for (name, body) in functions {
+        match link(body, options, Vec::new()) {
+            Ok(assembly) => {
+                // Functions without a declared signature take no arguments
+                let entry_point = entry_points
+                    .iter()
+                    .find(|entry_point| entry_point.label == name);
+                let signature = entry_point.map_or_else(
+                    || format!("{name}()"),
+                    |entry_point| entry_point.signature.clone(),
+                );
+                match ContractFunction::new(name, &signature, assembly.bytecode) {
+                    Ok(function) => compiled.push(function),
+                    // Only declared signatures can have types the ABI cannot describe
+                    Err(message) => diagnostics.push(match entry_point {
+                        Some(entry_point) => Diagnostic::error_at(&entry_point.span, message),
+                        None => Diagnostic::error(path, 0..0, message),
+                    }),
+                }
+            }
+            Err(errors) => diagnostics.extend(errors),
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    let name = Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned());
+    Ok(ContractArtifact {
+        name,
+        functions: compiled,
+    })
+}
+
+// Read and parse a file along with everything it includes, combined into a single AST
+fn load_file(
+    path: &String,
+    options: &CompileOptions,
+    loader: &dyn FileLoader,
+) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let unreadable = |error: std::io::Error| {
+        vec![Diagnostic::error(
+            path,
+            0..0,
+            format!("could not read `{path}`: {error}"),
+        )]
+    };
+    let file = loader.read(Path::new(path)).map_err(unreadable)?;
+
+    let mut fm = FileManager::new(options.include_paths.clone(), loader);
+    let root = fm.add_root(Path::new(path)).map_err(unreadable)?;
+    load(fm, root, &file)
+}
+
+// Parse source that was not read from a file, along with everything it includes
+fn load_source(
+    input: &str,
+    options: &CompileOptions,
+    loader: &dyn FileLoader,
+) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let mut fm = FileManager::new(options.include_paths.clone(), loader);
+    let root = fm.add_source_root(INPUT);
+    load(fm, root, input)
+}
+
+fn load(
+    mut fm: FileManager,
+    root: SourcePath,
+    file: &str,
+) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    let parsed = parse_asm(file, &root.name).unwrap_or_else(|errors| {
+        diagnostics.extend(errors);
+        Vec::new()
+    });
+    fm.extend_file_stack(&parsed, &[root], "", &mut diagnostics);
+
+    // Every file read, along with the namespace its definitions live in
+    let mut files = vec![(String::new(), parsed)];
 
     while !fm.is_empty() {
-        let next_file_contents = fm.get_next_file_contents();
-        let mut new_parsed = parse_asm(next_file_contents);
+        let included = match fm.get_next_file_contents() {
+            Ok(next) => next,
+            Err(error) => {
+                diagnostics.push(error);
+                continue;
+            }
+        };
+        let mut new_parsed = match parse_asm(&included.contents, &included.name) {
+            Ok(new_parsed) => new_parsed,
+            Err(errors) => {
+                diagnostics.extend(errors);
+                continue;
+            }
+        };
 
-        fm.extend_file_stack(&new_parsed);
-        new_parsed = new_parsed.into_iter().filter(|statement| !matches!(statement, Statement::IncludeStatement(_))).collect();
+        fm.extend_file_stack(
+            &new_parsed,
+            &included.include_chain,
+            &included.namespace,
+            &mut diagnostics,
+        );
+        new_parsed.retain(|statement| !matches!(statement.node, Statement::IncludeStatement(..)));
 
-        // Extend the AST with new file contents
-        parsed.extend(new_parsed);
+        files.push((included.namespace, new_parsed));
     }
 
-    process_asm(parsed)
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    // Combine every file into a single AST
+    Ok(resolve_namespaces(files))
 }
 
-pub fn compile_asm(input: String) -> String {
-    let parsed = parse_asm(input);
+#[cfg(not(target_arch = "wasm32"))]
+pub fn compile_asm(input: String) -> Result<String, Vec<Diagnostic>> {
+    compile_asm_with_options(input, &CompileOptions::default())
+}
 
-    process_asm(parsed)
+#[cfg(not(target_arch = "wasm32"))]
+/// Compile assembly source. Files it includes are read from disk, relative to the current
+/// directory, as `Assembler::assemble` does
+pub fn compile_asm_with_options(
+    input: String,
+    options: &CompileOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    assemble_source(&input, options, &FsLoader)
+        .map(|assembly| bytes_to_hex_string(&assembly.bytecode))
 }
 
-pub fn process_asm(mut parsed: Vec<Statement>) -> String {
+// Compile assembly source along with everything it includes, keeping what is needed to map the
+// program back to its source
+pub(crate) fn assemble_source(
+    input: &str,
+    options: &CompileOptions,
+    loader: &dyn FileLoader,
+) -> Result<Assembly, Vec<Diagnostic>> {
+    let parsed = load_source(input, options, loader)?;
+    assemble(parsed, options)
+}
 
+// The name given to assembly that is not read from a file
+pub(crate) const INPUT: &str = "<input>";
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Compile assembly source, writing the bytecode out in the given format. Files it includes are
+/// read from disk, relative to the current directory
+pub fn compile_asm_as(
+    input: String,
+    options: &CompileOptions,
+    format: OutputFormat,
+) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    assemble_source(&input, options, &FsLoader).map(|assembly| format.render(&assembly.bytecode))
+}
+
+// Run every compilation pass, collecting as many diagnostics as possible before bailing out
+pub fn process_asm(
+    parsed: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    assemble(parsed, options).map(|assembly| bytes_to_hex_string(&assembly.bytecode))
+}
+
+/// A compiled program along with where each of its instructions came from
+pub(crate) struct Assembly {
+    pub bytecode: Vec<u8>,
+    pub instructions: Vec<Instruction>,
+    // The byte offset each instruction starts at
+    pub offsets: Vec<usize>,
+    // The statement each instruction was written as, within any macro expansions
+    pub spans: Vec<Span>,
+    // The instruction each label refers to
+    pub labels: HashMap<String, u64>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+fn assemble(
+    parsed: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+) -> Result<Assembly, Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    let mut parsed = expand(parsed, options, &mut diagnostics);
+
+    // Route calls to the declared entry points before the program itself runs
+    let entry_points = take_entry_points(&mut parsed, &mut diagnostics);
+    if !entry_points.is_empty() {
+        let mut program = dispatcher(&entry_points, options.isa);
+        program.extend(parsed);
+        parsed = program;
+    }
+
+    link(parsed, options, diagnostics)
+}
+
+// Resolve constants and expand macros, leaving only labels and instructions
+fn expand(
+    parsed: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Spanned<Statement>> {
     // Resolve all constants
-    resolve_constants(&mut parsed);
+    let constants = resolve_constants(&parsed, &options.defines, diagnostics);
 
-    // TODO: remove to_vec
-    let mut parsed = resolve_macros(parsed.to_vec());
+    // Constants are substituted once macros have been expanded, so that they apply within macro
+    // bodies and to the arguments of invocations, while macro parameters shadow them
+    let mut parsed = resolve_macros(parsed, options, diagnostics);
+    substitute_constants(&mut parsed, &constants, diagnostics);
+    parsed
+}
 
+// Resolve labels and encode an expanded program, failing with any diagnostics collected so far
+fn link(
+    mut parsed: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+    mut diagnostics: Vec<Diagnostic>,
+) -> Result<Assembly, Vec<Diagnostic>> {
     // Resolve all static labels
-    resolve_labels(&mut parsed);
+    let labels = resolve_labels(&mut parsed, options.isa, &mut diagnostics);
+
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
 
     // Before we pass to the code generator, all we should have is a vector of opcodes
-    let instructions = temporary_to_instruction_vector(parsed);
-    generate_code(instructions)
+    let (instructions, spans) = temporary_to_instruction_vector(parsed);
+    let (bytecode, offsets) = encode(&instructions, options.isa).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|error| {
+                let (statement, operands) = &spans[error.index];
+                let span = error.operand.map_or(statement, |index| &operands[index]);
+                Diagnostic::error_at(span, error.message)
+            })
+            .collect::<Vec<_>>()
+    })?;
+
+    Ok(Assembly {
+        bytecode,
+        instructions,
+        offsets,
+        spans: spans.into_iter().map(|(span, _)| span).collect(),
+        labels,
+        warnings: diagnostics,
+    })
+}
+
+// Resolve namespaces
+//
+// Macros and constants defined in a file included `as` a namespace are renamed to
+// `namespace::name`. References are then looked up from the namespace of the file they appear in
+// outwards, ending with the global namespace
+fn resolve_namespaces(files: Vec<(String, Vec<Spanned<Statement>>)>) -> Vec<Spanned<Statement>> {
+    // First pass - collect the fully qualified name of every definition
+    let mut defined = HashSet::new();
+    for (namespace, statements) in files.iter() {
+        for statement in statements {
+            if let Statement::MacroStatement(name, ..) | Statement::ConstantDefinition(name, _) =
+                &statement.node
+            {
+                defined.insert(qualify(namespace, name));
+            }
+        }
+    }
+
+    // Second pass - rename definitions and references in place
+    files
+        .into_iter()
+        .flat_map(|(namespace, mut statements)| {
+            if !namespace.is_empty() {
+                for statement in statements.iter_mut() {
+                    qualify_statement(statement, &namespace, &defined, &[]);
+                }
+            }
+            statements
+        })
+        .collect()
+}
 
+fn qualify(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{namespace}::{name}")
+    }
+}
+
+// Find the definition a name refers to, searching outwards from the given namespace.
+// Names that are not defined anywhere are left alone to be reported later
+fn lookup(name: &str, namespace: &str, defined: &HashSet<String>) -> String {
+    let mut scope = namespace;
+    loop {
+        let candidate = qualify(scope, name);
+        if defined.contains(&candidate) {
+            return candidate;
+        }
+        if scope.is_empty() {
+            return name.to_owned();
+        }
+        scope = scope.rsplit_once("::").map_or("", |(parent, _)| parent);
+    }
+}
+
+fn qualify_statement(
+    statement: &mut Spanned<Statement>,
+    namespace: &str,
+    defined: &HashSet<String>,
+    parameters: &[MacroParameter],
+) {
+    // Macro parameters shadow constants, so are never renamed
+    let qualify_operand = |operand: &mut Operand| {
+        operand.substitute_variables(&mut |name| {
+            let is_parameter = parameters
+                .iter()
+                .any(|parameter| parameter.name.node == name);
+            (!is_parameter).then(|| Operand::Variable(lookup(name, namespace, defined)))
+        })
+    };
+
+    match &mut statement.node {
+        Statement::MacroStatement(name, parameters, body) => {
+            *name = qualify(namespace, name);
+            for statement in body.iter_mut() {
+                qualify_statement(statement, namespace, defined, parameters);
+            }
+        }
+        Statement::ConstantDefinition(name, value) => {
+            *name = qualify(namespace, name);
+            qualify_operand(&mut value.node);
+        }
+        Statement::MacroInvocation(name, arguments) => {
+            *name = lookup(name, namespace, defined);
+            for argument in arguments.iter_mut() {
+                if let MacroArgument::Operand(operand) = &mut argument.node {
+                    qualify_operand(operand);
+                }
+            }
+        }
+        Statement::OpcodeStatement(_, _, operands, _) => {
+            for operand in operands.iter_mut() {
+                qualify_operand(&mut operand.node);
+            }
+        }
+        _ => {}
+    }
 }
 
 // Resolve constants
 //
 // This algorithm involves two passes:
 // 1. collect all constant definitions into a hash map
-// 2. Find all invocations of constants and replace them with the value
-fn resolve_constants(parsed: &mut [Statement]) {
-    let mut constants: HashMap<String, Operand> = HashMap::new();
+// 2. resolve each definition's value, following references to other constants in any order
+//
+// Constants that could not be resolved are recorded as `None`, so they are only reported once
+fn resolve_constants(
+    parsed: &[Spanned<Statement>],
+    defines: &BTreeMap<String, BigUint>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, Option<Operand>> {
+    let mut definitions: HashMap<String, ConstantDefinition> = HashMap::new();
+    let mut order = Vec::new();
+
+    // Defines are resolved up front, so any constant may refer to them
+    let mut resolved: HashMap<String, Option<Operand>> = defines
+        .iter()
+        .map(|(name, value)| (name.clone(), Some(Operand::Hex(format!("0x{value:x}")))))
+        .collect();
 
     for statement in parsed.iter() {
-        if let Statement::ConstantDefinition(name, value) = statement {
-            constants.insert(name.clone(), value.clone());
+        if let Statement::ConstantDefinition(name, value) = &statement.node {
+            if defines.contains_key(name) {
+                diagnostics.push(
+                    Diagnostic::error_at(
+                        &statement.span,
+                        format!("constant `${name}` is defined multiple times"),
+                    )
+                    .with_note(format!("`${name}` is also defined by the compile options")),
+                );
+                continue;
+            }
+            if let Some(previous) = definitions.get(name) {
+                diagnostics.push(
+                    Diagnostic::error_at(
+                        &statement.span,
+                        format!("constant `${name}` is defined multiple times"),
+                    )
+                    .with_label(&previous.span, "previously defined here"),
+                );
+                continue;
+            }
+            definitions.insert(
+                name.clone(),
+                ConstantDefinition {
+                    value: value.clone(),
+                    span: statement.span.clone(),
+                },
+            );
+            order.push(name.clone());
         }
     }
 
-    // Resolve all variable definitions in our operands and replace with that valid constants
-    // We do this inplace
+    for name in &order {
+        resolve_constant(
+            name,
+            &definitions,
+            &mut resolved,
+            &mut Vec::new(),
+            diagnostics,
+        );
+    }
+
+    resolved
+}
+
+struct ConstantDefinition {
+    value: Spanned<Operand>,
+    span: Span,
+}
+
+// Resolve the value of a constant, first resolving any constants it is defined in terms of.
+// `stack` holds the constants currently being resolved, so that cycles can be detected
+fn resolve_constant(
+    name: &str,
+    definitions: &HashMap<String, ConstantDefinition>,
+    resolved: &mut HashMap<String, Option<Operand>>,
+    stack: &mut Vec<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Operand> {
+    if let Some(value) = resolved.get(name) {
+        return value.clone();
+    }
+    let definition = definitions.get(name)?;
+
+    if let Some(start) = stack.iter().position(|pending| pending == name) {
+        let cycle = stack[start..]
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .map(|name| format!("${name}"))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        diagnostics.push(
+            Diagnostic::error_at(
+                &definition.span,
+                format!("constant `${name}` is defined in terms of itself"),
+            )
+            .with_note(format!("reference cycle: {cycle}")),
+        );
+        return None;
+    }
+
+    stack.push(name.to_owned());
+    let mut value = definition.value.node.clone();
+    let mut is_valid = true;
+    value.substitute_variables(&mut |reference| {
+        if !definitions.contains_key(reference) && !resolved.contains_key(reference) {
+            diagnostics.push(Diagnostic::error_at(
+                &definition.value.span,
+                format!("constant `${reference}` is not defined"),
+            ));
+        }
+        let value = resolve_constant(reference, definitions, resolved, stack, diagnostics);
+        is_valid &= value.is_some();
+        value
+    });
+    stack.pop();
+
+    let value = is_valid.then_some(value);
+    resolved.insert(name.to_owned(), value.clone());
+    value
+}
+
+// Find all invocations of constants and replace them with the value
+// We do this inplace, keeping the span of the use site
+fn substitute_constants(
+    parsed: &mut [Spanned<Statement>],
+    constants: &HashMap<String, Option<Operand>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
     for statement in parsed.iter_mut() {
-        match statement {
-            Statement::OpcodeStatement(_, _, operands, _) => {
-                for operand in operands.iter_mut() {
-                    if let Operand::Variable(name) = operand {
-                        if let Some(constant) = constants.get(name) {
-                            *operand = constant.clone();
+        if let Statement::OpcodeStatement(_, _, operands, _) = &mut statement.node {
+            for operand in operands.iter_mut() {
+                let span = &operand.span;
+                operand
+                    .node
+                    .substitute_variables(&mut |name| match constants.get(name) {
+                        Some(value) => value.clone(),
+                        // Constants that are defined but broken have already been reported
+                        None => {
+                            diagnostics.push(Diagnostic::error_at(
+                                span,
+                                format!("constant `${name}` is not defined"),
+                            ));
+                            None
                         }
-                    }
-                }
+                    });
             }
-            _ => {}
         }
     }
 }
@@ -91,17 +652,63 @@ fn resolve_constants(parsed: &mut [Statement]) {
 // This algorithm involves two passes:
 // 1. collect all macro definitions into a hash map
 // 2. resolve all macro invocations
-fn resolve_macros(parsed: Vec<Statement>) -> Vec<Statement> {
-    let macro_definitions = collect_macro_definitions(&parsed);
-    expand_macros(parsed, &macro_definitions)
+fn resolve_macros(
+    parsed: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Spanned<Statement>> {
+    let macro_definitions = collect_macro_definitions(&parsed, diagnostics);
+    expand_macros(parsed, &macro_definitions, options, diagnostics)
+}
+
+struct MacroDefinition {
+    parameters: Vec<MacroParameter>,
+    body: Vec<Spanned<Statement>>,
+    span: Span,
 }
 
-fn collect_macro_definitions(parsed: &[Statement]) -> HashMap<String, Vec<Statement>> {
-    let mut macro_definitions: HashMap<String, Vec<Statement>> = HashMap::new();
+fn collect_macro_definitions(
+    parsed: &[Spanned<Statement>],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, MacroDefinition> {
+    let mut macro_definitions: HashMap<String, MacroDefinition> = HashMap::new();
 
     for statement in parsed.iter() {
-        if let Statement::MacroStatement(name, statements) = statement {
-            macro_definitions.insert(name.clone(), statements.clone());
+        if let Statement::MacroStatement(name, parameters, statements) = &statement.node {
+            for (i, parameter) in parameters.iter().enumerate() {
+                if let Some(previous) = parameters[..i]
+                    .iter()
+                    .find(|previous| previous.name.node == parameter.name.node)
+                {
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &parameter.name.span,
+                            format!("parameter `{}` is declared twice", parameter.name.node),
+                        )
+                        .with_label(&previous.name.span, "first declared here"),
+                    );
+                }
+            }
+
+            if let Some(previous) = macro_definitions.get(name) {
+                diagnostics.push(
+                    Diagnostic::error_at(
+                        &statement.span,
+                        format!("macro `${name}` is defined multiple times"),
+                    )
+                    .with_label(&previous.span, "previously defined here"),
+                );
+                continue;
+            }
+
+            macro_definitions.insert(
+                name.clone(),
+                MacroDefinition {
+                    parameters: parameters.clone(),
+                    body: statements.clone(),
+                    span: statement.span.clone(),
+                },
+            );
         }
     }
 
@@ -112,28 +719,107 @@ fn collect_macro_definitions(parsed: &[Statement]) -> HashMap<String, Vec<Statem
 //
 // Expand macros using a stack based approach, to handle nested macro definitions
 fn expand_macros(
-    parsed: Vec<Statement>,
-    macro_definitions: &HashMap<String, Vec<Statement>>,
-) -> Vec<Statement> {
-    let mut resolved: Vec<Statement> = Vec::new();
+    parsed: Vec<Spanned<Statement>>,
+    macro_definitions: &HashMap<String, MacroDefinition>,
+    options: &CompileOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Spanned<Statement>> {
+    let mut resolved = Vec::new();
     let mut stack = VecDeque::new();
+    let mut expansions = 0;
 
-    // Push ast nodes onto stack in reverse, without macro defs
+    // Push ast nodes onto stack in reverse, without macro defs.
+    // Each node is paired with the names of the macros it was expanded through
+    let top_level: Rc<Vec<String>> = Rc::default();
     for node in parsed
-        .iter()
-        .filter(|node| !matches!(node, Statement::MacroStatement(_, _)))
+        .into_iter()
+        .filter(|node| !matches!(node.node, Statement::MacroStatement(..)))
         .rev()
     {
-        stack.push_back(node.clone());
+        stack.push_back((node, top_level.clone()));
     }
 
-    while let Some(node) = stack.pop_back() {
-        match node {
-            Statement::MacroInvocation(name) => {
-                // TODO: dont panic if macro is undefined
-                let macro_def = macro_definitions.get(&name).unwrap();
-                for statement in macro_def.iter().rev() {
-                    stack.push_back(statement.clone());
+    while let Some((mut node, macro_chain)) = stack.pop_back() {
+        // Local labels outside of a macro body have no expansion to belong to
+        if node.span.expansion.is_none() {
+            let mut misused = false;
+            for_each_label(&mut node, |label, span| {
+                if is_local_label(label) {
+                    diagnostics.push(Diagnostic::error_at(
+                        span,
+                        format!("local label `{label}` can only be used inside a macro"),
+                    ));
+                    misused = true;
+                }
+            });
+            if misused {
+                continue;
+            }
+        }
+
+        match &node.node {
+            Statement::MacroInvocation(name, arguments) => {
+                let Some(macro_def) = macro_definitions.get(name) else {
+                    diagnostics.push(Diagnostic::error_at(
+                        &node.span,
+                        format!("macro `${name}` is not defined"),
+                    ));
+                    continue;
+                };
+
+                if macro_chain.contains(name) {
+                    let cycle = macro_chain
+                        .iter()
+                        .skip_while(|invoked| *invoked != name)
+                        .chain([name])
+                        .map(|invoked| format!("${invoked}"))
+                        .collect::<Vec<_>>()
+                        .join(" -> ");
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &node.span,
+                            format!("macro `${name}` invokes itself recursively"),
+                        )
+                        .with_note(format!("expansion cycle: {cycle}")),
+                    );
+                    continue;
+                }
+                if macro_chain.len() >= options.max_expansion_depth {
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &node.span,
+                            format!(
+                                "macro expansion exceeds the maximum depth of {}",
+                                options.max_expansion_depth
+                            ),
+                        )
+                        .with_note(format!("expanded through ${}", macro_chain.join(" -> $"))),
+                    );
+                    continue;
+                }
+
+                let Some(bindings) =
+                    bind_arguments(name, &node.span, arguments, macro_def, diagnostics)
+                else {
+                    continue;
+                };
+
+                // Each expansion gets its own copy of the local labels in the body
+                expansions += 1;
+                let invocation = Rc::new(node.span.clone());
+                let mut body_chain = macro_chain.as_ref().clone();
+                body_chain.push(name.clone());
+                let body_chain = Rc::new(body_chain);
+
+                for statement in macro_def.body.iter().rev() {
+                    let mut statement = expanded_from(statement, &invocation);
+                    for_each_label(&mut statement, |label, _| {
+                        if is_local_label(label) {
+                            label.push_str(&format!("#{expansions}"));
+                        }
+                    });
+                    substitute_arguments(&mut statement, &bindings, diagnostics);
+                    stack.push_back((statement, body_chain.clone()));
                 }
             }
             _ => resolved.push(node),
@@ -143,20 +829,242 @@ fn expand_macros(
     resolved
 }
 
+// Match the arguments of an invocation against the parameters of the macro, checking their
+// count and kinds
+fn bind_arguments<'a>(
+    name: &str,
+    invocation: &Span,
+    arguments: &'a [Spanned<MacroArgument>],
+    macro_def: &'a MacroDefinition,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<HashMap<&'a str, &'a Spanned<MacroArgument>>> {
+    if arguments.len() != macro_def.parameters.len() {
+        let plural = if macro_def.parameters.len() == 1 {
+            ""
+        } else {
+            "s"
+        };
+        diagnostics.push(
+            Diagnostic::error_at(
+                invocation,
+                format!(
+                    "macro `${name}` expects {} argument{plural}, but {} {} given",
+                    macro_def.parameters.len(),
+                    arguments.len(),
+                    if arguments.len() == 1 { "was" } else { "were" },
+                ),
+            )
+            .with_label(&macro_def.span, format!("macro `${name}` defined here")),
+        );
+        return None;
+    }
+
+    let mut bindings = HashMap::new();
+    let mut valid = true;
+    for (parameter, argument) in macro_def.parameters.iter().zip(arguments) {
+        if parameter.kind != argument.node.kind() {
+            diagnostics.push(
+                Diagnostic::error_at(
+                    &argument.span,
+                    format!(
+                        "argument `{}` of macro `${name}` expects {}, but {} was given",
+                        parameter.name.node,
+                        with_article(parameter.kind),
+                        with_article(argument.node.kind()),
+                    ),
+                )
+                .with_label(&parameter.name.span, "parameter declared here"),
+            );
+            valid = false;
+        }
+        bindings.insert(parameter.name.node.as_str(), argument);
+    }
+
+    valid.then_some(bindings)
+}
+
+fn with_article(kind: ParameterKind) -> &'static str {
+    match kind {
+        ParameterKind::Operand => "an operand",
+        ParameterKind::Label => "a label",
+    }
+}
+
+// Replace references to macro parameters with the arguments they were bound to.
+// Operand parameters are referred to as `$name`, label parameters as `@name`
+fn substitute_arguments(
+    statement: &mut Spanned<Statement>,
+    bindings: &HashMap<&str, &Spanned<MacroArgument>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    match &mut statement.node {
+        Statement::OpcodeStatement(_, _, operands, label) => {
+            for operand in operands.iter_mut() {
+                substitute_operand(&mut operand.node, &mut operand.span, bindings, diagnostics);
+            }
+            if let Some(label) = label {
+                substitute_label(&mut label.node, &mut label.span, bindings, diagnostics);
+            }
+        }
+        Statement::MacroInvocation(_, arguments) => {
+            for argument in arguments.iter_mut() {
+                match &mut argument.node {
+                    MacroArgument::Operand(operand) => {
+                        substitute_operand(operand, &mut argument.span, bindings, diagnostics)
+                    }
+                    MacroArgument::Label(label) => {
+                        substitute_label(label, &mut argument.span, bindings, diagnostics)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn substitute_operand(
+    operand: &mut Operand,
+    span: &mut Span,
+    bindings: &HashMap<&str, &Spanned<MacroArgument>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let label_parameter_error = |name: &str, span: &Span| {
+        Diagnostic::error_at(span, format!("`${name}` is a label parameter"))
+            .with_note(format!("refer to label parameters as `@{name}`"))
+    };
+
+    let Operand::Variable(name) = operand else {
+        // Parameters used within an expression are replaced in place, keeping the expression's
+        // span
+        operand.substitute_variables(&mut |name| match bindings.get(name) {
+            Some(Spanned {
+                node: MacroArgument::Operand(value),
+                ..
+            }) => Some(value.clone()),
+            Some(_) => {
+                diagnostics.push(label_parameter_error(name, span));
+                None
+            }
+            None => None,
+        });
+        return;
+    };
+    match bindings.get(name.as_str()) {
+        Some(Spanned {
+            node: MacroArgument::Operand(value),
+            span: argument_span,
+        }) => {
+            *operand = value.clone();
+            *span = argument_span.clone();
+        }
+        Some(_) => diagnostics.push(label_parameter_error(name, span)),
+        None => {}
+    }
+}
+
+fn substitute_label(
+    label: &mut String,
+    span: &mut Span,
+    bindings: &HashMap<&str, &Spanned<MacroArgument>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    match bindings.get(label.as_str()) {
+        Some(Spanned {
+            node: MacroArgument::Label(target),
+            span: argument_span,
+        }) => {
+            *label = target.clone();
+            *span = argument_span.clone();
+        }
+        Some(_) => diagnostics.push(
+            Diagnostic::error_at(span, format!("`@{label}` is an operand parameter"))
+                .with_note(format!("refer to operand parameters as `${label}`")),
+        ),
+        None => {}
+    }
+}
+
+// Local labels are written `%name`, and are renamed to `%name#<expansion>` once expanded
+fn is_local_label(label: &str) -> bool {
+    label.starts_with('%') && !label.contains('#')
+}
+
+// The label as the user wrote it, without any expansion suffix
+pub(crate) fn display_label(label: &str) -> &str {
+    label.split('#').next().unwrap_or(label)
+}
+
+// Visit every label a statement defines or refers to
+fn for_each_label(statement: &mut Spanned<Statement>, mut f: impl FnMut(&mut String, &Span)) {
+    match &mut statement.node {
+        Statement::Label(label) => f(label, &statement.span),
+        Statement::OpcodeStatement(_, _, _, Some(label)) => f(&mut label.node, &label.span),
+        Statement::MacroInvocation(_, arguments) => {
+            for argument in arguments.iter_mut() {
+                if let MacroArgument::Label(label) = &mut argument.node {
+                    f(label, &argument.span);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Copy a statement out of a macro body, recording the invocation on every span within it
+fn expanded_from(statement: &Spanned<Statement>, invocation: &Rc<Span>) -> Spanned<Statement> {
+    let mut statement = statement.clone();
+    statement.span = statement.span.expanded_from(invocation);
+
+    match &mut statement.node {
+        Statement::OpcodeStatement(_, _, operands, label) => {
+            for operand in operands.iter_mut() {
+                operand.span = operand.span.expanded_from(invocation);
+            }
+            if let Some(label) = label {
+                label.span = label.span.expanded_from(invocation);
+            }
+        }
+        Statement::MacroInvocation(_, arguments) => {
+            for argument in arguments.iter_mut() {
+                argument.span = argument.span.expanded_from(invocation);
+            }
+        }
+        _ => {}
+    }
+
+    statement
+}
+
 // Resolve labels
 //
 // This algorithm involves two passes:
 // 1. Collect all of the labels
 // 2. Resolve the labels in place
-fn resolve_labels(parsed: &mut [Statement]) {
-    let mut label_map: HashMap<String, u64> = HashMap::new();
+//
+// Returns the instruction each label refers to
+fn resolve_labels(
+    parsed: &mut [Spanned<Statement>],
+    isa: Isa,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, u64> {
+    let mut label_map: HashMap<String, (u64, Span)> = HashMap::new();
 
     // First pass - label collection
     let mut pc = 0;
-    for ref statement in parsed.iter_mut() {
-        match statement {
+    for statement in parsed.iter() {
+        match &statement.node {
             Statement::Label(label) => {
-                label_map.insert(label.clone(), pc);
+                if let Some((_, previous)) = label_map.get(label) {
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &statement.span,
+                            format!("label `{}` is defined multiple times", display_label(label)),
+                        )
+                        .with_label(previous, "previously defined here"),
+                    );
+                    continue;
+                }
+                label_map.insert(label.clone(), (pc, statement.span.clone()));
             }
             Statement::OpcodeStatement(_, _, _, _) => {
                 pc += 1;
@@ -168,40 +1076,339 @@ fn resolve_labels(parsed: &mut [Statement]) {
 
     // Second pass - label resolution
     for statement in parsed.iter_mut() {
-        match statement {
-            Statement::Label(_) => {}
-            Statement::OpcodeStatement(_, _, operands, label) => {
-                // TODO: error handling for unmatched label
-                // TODO: make sure the code with the label IS a JUMP
-                if let Some(ref label) = label {
-                    let resolved_label = label_map.get(label).unwrap();
-                    // If it is a jump then we push into the front
-                    operands.insert(0, (*resolved_label).into());
+        if let Statement::OpcodeStatement(opcode, _, operands, Some(label)) = &mut statement.node {
+            // Labels are only ever given as the first operand. Opcodes missing from the instruction
+            // set are reported when they are encoded
+            let takes_label = isa.spec(*opcode).is_none_or(|spec| {
+                spec.operands
+                    .first()
+                    .is_some_and(|operand| operand.kind == OperandKind::JumpTarget)
+            });
+            if !takes_label {
+                diagnostics.push(Diagnostic::error_at(
+                    &label.span,
+                    format!("{} does not take a label", opcode.name()),
+                ));
+                continue;
+            }
+
+            match label_map.get(&label.node) {
+                // If it is a jump then we push into the front
+                Some((resolved_label, _)) => operands.insert(
+                    0,
+                    Spanned::new((*resolved_label).into(), label.span.clone()),
+                ),
+                None => diagnostics.push(Diagnostic::error_at(
+                    &label.span,
+                    format!("label `{}` is not defined", display_label(&label.node)),
+                )),
+            }
+        }
+    }
+
+    label_map
+        .into_iter()
+        .map(|(label, (pc, _))| (label, pc))
+        .collect()
+}
+
+// A function declared with `.entry`
+struct EntryPoint {
+    label: String,
+    signature: String,
+    selector: u32,
+    span: Span,
+}
+
+// Remove the `.entry` directives from an expanded program, leaving out those that have no label to
+// route calls to
+fn take_entry_points(
+    parsed: &mut Vec<Spanned<Statement>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<EntryPoint> {
+    // Labels written in macros are renamed by each expansion, so only top level ones can match
+    let labels: HashSet<String> = parsed
+        .iter()
+        .filter_map(|statement| match &statement.node {
+            Statement::Label(label) if statement.span.expansion.is_none() => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut entry_points: Vec<EntryPoint> = Vec::new();
+    parsed.retain(|statement| {
+        let Statement::EntryPoint(label, signature) = &statement.node else {
+            return true;
+        };
+
+        let selector = selector(signature);
+        if let Some(previous) = entry_points.iter().find(|entry| entry.label == *label) {
+            diagnostics.push(
+                Diagnostic::error_at(
+                    &statement.span,
+                    format!("entry point `{label}` is declared multiple times"),
+                )
+                .with_label(&previous.span, "previously declared here"),
+            );
+        } else if let Some(previous) = entry_points.iter().find(|entry| entry.selector == selector)
+        {
+            diagnostics.push(
+                Diagnostic::error_at(
+                    &statement.span,
+                    format!(
+                        "the selector of `{signature}` is the same as that of `{}`",
+                        previous.signature
+                    ),
+                )
+                .with_label(&previous.span, "previously declared here"),
+            );
+        } else {
+            entry_points.push(EntryPoint {
+                label: label.clone(),
+                signature: signature.clone(),
+                selector,
+                span: statement.span.clone(),
+            });
+        }
+        false
+    });
+
+    entry_points.retain(|entry_point| {
+        let found = labels.contains(&entry_point.label);
+        if !found {
+            diagnostics.push(Diagnostic::error_at(
+                &entry_point.span,
+                format!("entry point `{}` has no matching label", entry_point.label),
+            ));
+        }
+        found
+    });
+    entry_points
+}
+
+// The memory the dispatcher reads the selector into, and its scratch space
+const SELECTOR_OFFSET: u64 = 0;
+const SCRATCH_OFFSET: u64 = 1;
+const CONDITION_OFFSET: u64 = 2;
+
+// Instructions that read the selector from the first field of calldata and jump to the entry point
+// it matches, reverting if there is none. Each instruction is attributed to the directive it
+// implements
+fn dispatcher(entry_points: &[EntryPoint], isa: Isa) -> Vec<Spanned<Statement>> {
+    let instruction = |span: &Span, opcode, operands: Vec<Operand>, label: Option<&String>| {
+        let operands = operands
+            .into_iter()
+            .map(|operand| Spanned::new(operand, span.clone()))
+            .collect();
+        let label = label.map(|label| Spanned::new(label.clone(), span.clone()));
+        Spanned::new(
+            Statement::OpcodeStatement(opcode, false, operands, label),
+            span.clone(),
+        )
+    };
+
+    let first = &entry_points[0].span;
+    // Profiles either take the calldata offset and number of fields inline or read them from memory
+    let from_memory = isa
+        .spec(Opcode::CALLDATACOPY)
+        .is_some_and(|spec| spec.operands[0].kind == OperandKind::Memory);
+    let mut dispatcher = match from_memory {
+        false => vec![instruction(
+            first,
+            Opcode::CALLDATACOPY,
+            vec![0.into(), 1.into(), SELECTOR_OFFSET.into()],
+            None,
+        )],
+        true => vec![
+            instruction(
+                first,
+                Opcode::SET,
+                vec![Operand::Tag(TypeTag::U32), 0.into(), SCRATCH_OFFSET.into()],
+                None,
+            ),
+            instruction(
+                first,
+                Opcode::SET,
+                vec![
+                    Operand::Tag(TypeTag::U32),
+                    1.into(),
+                    CONDITION_OFFSET.into(),
+                ],
+                None,
+            ),
+            instruction(
+                first,
+                Opcode::CALLDATACOPY,
+                vec![
+                    SCRATCH_OFFSET.into(),
+                    CONDITION_OFFSET.into(),
+                    SELECTOR_OFFSET.into(),
+                ],
+                None,
+            ),
+        ],
+    };
+
+    for entry_point in entry_points {
+        let span = &entry_point.span;
+        let selector = Operand::Hex(format!("0x{:x}", entry_point.selector));
+        dispatcher.extend([
+            instruction(
+                span,
+                Opcode::SET,
+                vec![Operand::Tag(TypeTag::FF), selector, SCRATCH_OFFSET.into()],
+                None,
+            ),
+            instruction(
+                span,
+                Opcode::EQ,
+                vec![
+                    SELECTOR_OFFSET.into(),
+                    SCRATCH_OFFSET.into(),
+                    CONDITION_OFFSET.into(),
+                ],
+                None,
+            ),
+            instruction(
+                span,
+                Opcode::JUMPI,
+                vec![CONDITION_OFFSET.into()],
+                Some(&entry_point.label),
+            ),
+        ]);
+    }
+
+    dispatcher.push(instruction(
+        first,
+        Opcode::REVERT,
+        vec![0.into(), 0.into()],
+        None,
+    ));
+    dispatcher
+}
+
+// Split an expanded program into the functions of a contract, one per entry point. Labels are
+// resolved within each function, so jumps may not cross from one function into another
+fn split_functions(
+    parsed: Vec<Spanned<Statement>>,
+    path: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<(String, Vec<Spanned<Statement>>)> {
+    let jumped_to: HashSet<String> = parsed
+        .iter()
+        .filter_map(|statement| match &statement.node {
+            Statement::OpcodeStatement(_, _, _, Some(label)) => Some(label.node.clone()),
+            _ => None,
+        })
+        .collect();
+    let is_entry_point = |statement: &Spanned<Statement>| match &statement.node {
+        Statement::Label(label) => {
+            statement.span.expansion.is_none()
+                && &*statement.span.file == path
+                && !jumped_to.contains(label)
+        }
+        _ => false,
+    };
+
+    let mut functions: Vec<(String, Vec<Spanned<Statement>>)> = Vec::new();
+    let mut entry_points: HashMap<String, Span> = HashMap::new();
+    for statement in parsed {
+        if is_entry_point(&statement) {
+            let Statement::Label(label) = &statement.node else {
+                unreachable!()
+            };
+            if let Some(previous) = entry_points.get(label) {
+                diagnostics.push(
+                    Diagnostic::error_at(
+                        &statement.span,
+                        format!("label `{label}` is defined multiple times"),
+                    )
+                    .with_label(previous, "previously defined here"),
+                );
+            }
+            entry_points.insert(label.clone(), statement.span.clone());
+            functions.push((label.clone(), Vec::new()));
+        }
+
+        match functions.last_mut() {
+            Some((_, body)) => body.push(statement),
+            None if matches!(statement.node, Statement::OpcodeStatement(..)) => {
+                diagnostics.push(Diagnostic::error_at(
+                    &statement.span,
+                    "instructions must follow an entry point when compiling a contract",
+                ));
+            }
+            None => {}
+        }
+    }
+
+    // Point jumps into other functions at the function they would have to leave for
+    let owners: HashMap<&String, &String> = functions
+        .iter()
+        .flat_map(|(name, body)| {
+            body.iter()
+                .filter_map(move |statement| match &statement.node {
+                    Statement::Label(label) => Some((label, name)),
+                    _ => None,
+                })
+        })
+        .collect();
+    let mut crossings = Vec::new();
+    for (name, body) in &functions {
+        for statement in body {
+            if let Statement::OpcodeStatement(_, _, _, Some(label)) = &statement.node {
+                match owners.get(&label.node) {
+                    Some(&owner) if owner != name => crossings.push(Diagnostic::error_at(
+                        &label.span,
+                        format!(
+                            "label `{}` belongs to function `{owner}`, jumps cannot leave `{name}`",
+                            display_label(&label.node)
+                        ),
+                    )),
+                    _ => {}
                 }
             }
-            _ => {}
         }
     }
+    if !crossings.is_empty() {
+        diagnostics.extend(crossings);
+        return Vec::new();
+    }
+
+    functions
 }
 
 // This will be replaced with methods that resolve
 // 1. labels
 // 2. macros
-fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
+//
+// Returns the instructions along with the span each one, and each of its operands, was written at
+fn temporary_to_instruction_vector(
+    parsed: Vec<Spanned<Statement>>,
+) -> (Vec<Instruction>, Vec<(Span, Vec<Span>)>) {
     let mut instructions = Vec::new();
+    let mut spans = Vec::new();
 
     for statement in parsed {
-        if let Statement::OpcodeStatement(opcode, indirect, operands, _) = statement {
+        if let Statement::OpcodeStatement(opcode, indirect, operands, _) = statement.node {
             // At this point labels should have been resolved!
+            let (operands, operand_spans) = operands
+                .into_iter()
+                .map(|operand| (operand.node, operand.span))
+                .unzip();
             let instr = Instruction::new(opcode, indirect, operands);
             instructions.push(instr);
+            spans.push((statement.span, operand_spans));
         }
     }
 
-    println!("{:?}", instructions);
-    instructions
+    (instructions, spans)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codegen::generate_code, loader::MemoryLoader, opcodes::Opcode, parser::TypeTag};
 
     #[test]
     fn simple_test() {
@@ -212,7 +1419,7 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(input);
+        let bytecode = compile_asm(input).unwrap();
 
         assert_eq!(bytecode, "00000000000000000001000000000000000200000000000000030101000000000000000100000000000000020000000000000003");
     }
@@ -235,9 +1442,9 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
             Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
             Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
         ];
-        let expected_bytecode = generate_code(expected_instructions);
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
 
-        let bytecode = compile_asm(input);
+        let bytecode = compile_asm(input).unwrap();
         assert_eq!(bytecode, expected_bytecode);
     }
 
@@ -257,9 +1464,9 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
             Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
             Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
         ];
-        let expected_bytecode = generate_code(expected_instructions);
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
 
-        let bytecode = compile_asm(input);
+        let bytecode = compile_asm(input).unwrap();
         assert_eq!(bytecode, expected_bytecode);
     }
 
@@ -285,9 +1492,9 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
             Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
             Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
         ];
-        let expected_bytecode = generate_code(expected_instructions);
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
 
-        let bytecode = compile_asm(input);
+        let bytecode = compile_asm(input).unwrap();
         assert_eq!(bytecode, expected_bytecode);
     }
 
@@ -313,11 +1520,378 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
             Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
         ];
 
-        let expected_bytecode = generate_code(expected_instructions);
-        let bytecode = compile_asm(input);
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
+        let bytecode = compile_asm(input).unwrap();
+        assert_eq!(bytecode, expected_bytecode);
+    }
+
+    #[test]
+    fn macro_with_arguments() {
+        let input = "
+            .macro copy(src, dst) {
+                mov $src $dst;
+            };
+
+            $copy(1, 2);
+            $copy(0x3, 4);
+        "
+        .to_owned();
+
+        let expected_instructions = vec![
+            Instruction::new(Opcode::MOV, false, vec![1.into(), 2.into()]),
+            Instruction::new(Opcode::MOV, false, vec![3.into(), 4.into()]),
+        ];
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
+
+        let bytecode = compile_asm(input).unwrap();
+        assert_eq!(bytecode, expected_bytecode);
+    }
+
+    #[test]
+    fn macro_label_arguments_and_nested_invocations() {
+        let input = "
+            .macro goto(cond, target: label) {
+                jumpi @target $cond;
+            };
+
+            .macro copy_or_skip(value, skip: label) {
+                $goto($value, @skip);
+                mov $value 5;
+            };
+
+            $copy_or_skip(7, @end);
+            add 1 2 3;
+        end:
+            sub 1 2 3;
+        "
+        .to_owned();
+
+        let expected_instructions = vec![
+            Instruction::new(Opcode::JUMPI, false, vec![3.into(), 7.into()]),
+            Instruction::new(Opcode::MOV, false, vec![7.into(), 5.into()]),
+            Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
+            Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
+        ];
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
+
+        let bytecode = compile_asm(input).unwrap();
+        assert_eq!(bytecode, expected_bytecode);
+    }
+
+    #[test]
+    fn macro_argument_errors() {
+        let input = "
+            .macro copy(src, dst) {
+                mov $src $dst;
+            };
+            .macro goto(target: label) {
+                jump @target;
+            };
+
+            $copy(1);
+            $goto(1);
+            $copy;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "macro `$copy` expects 2 arguments, but 1 was given",
+                "argument `target` of macro `$goto` expects a label, but an operand was given",
+                "macro `$copy` expects 2 arguments, but 0 were given",
+            ]
+        );
+        assert_eq!(&input[errors[1].span.clone()], "1");
+        assert_eq!(&input[errors[1].labels[0].span.clone()], "target");
+    }
+
+    #[test]
+    fn macro_local_labels_are_unique_per_expansion() {
+        let input = "
+            .macro countdown(counter) {
+            %loop:
+                sub $counter 1 $counter;
+                jumpi @%loop $counter;
+                jump @done;
+            };
+
+            $countdown(1);
+            $countdown(2);
+        done:
+            return 0 0;
+        "
+        .to_owned();
+
+        let expected_instructions = vec![
+            Instruction::new(Opcode::SUB, false, vec![1.into(), 1.into(), 1.into()]),
+            Instruction::new(Opcode::JUMPI, false, vec![0.into(), 1.into()]),
+            Instruction::new(Opcode::JUMP, false, vec![6.into()]),
+            Instruction::new(Opcode::SUB, false, vec![2.into(), 1.into(), 2.into()]),
+            Instruction::new(Opcode::JUMPI, false, vec![3.into(), 2.into()]),
+            Instruction::new(Opcode::JUMP, false, vec![6.into()]),
+            Instruction::new(Opcode::RETURN, false, vec![0.into(), 0.into()]),
+        ];
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
+
+        let bytecode = compile_asm(input).unwrap();
         assert_eq!(bytecode, expected_bytecode);
     }
 
+    #[test]
+    fn local_labels_can_be_passed_to_nested_macros() {
+        let input = "
+            .macro goto(target: label) {
+                jump @target;
+            };
+
+            .macro spin {
+            %top:
+                $goto(@%top);
+            };
+
+            add 1 2 3;
+            $spin;
+            $spin;
+        "
+        .to_owned();
+
+        let expected_instructions = vec![
+            Instruction::new(Opcode::ADD, false, vec![1.into(), 2.into(), 3.into()]),
+            Instruction::new(Opcode::JUMP, false, vec![1.into()]),
+            Instruction::new(Opcode::JUMP, false, vec![2.into()]),
+        ];
+        let expected_bytecode = generate_code(expected_instructions, Isa::V1).unwrap();
+
+        let bytecode = compile_asm(input).unwrap();
+        assert_eq!(bytecode, expected_bytecode);
+    }
+
+    #[test]
+    fn label_errors() {
+        let input = "
+            .macro global_label {
+            shared:
+                add 1 2 3;
+            };
+
+            .macro bad_local {
+                jump @%missing;
+            };
+
+            $global_label;
+            $global_label;
+            $bad_local;
+        top:
+            jump @%top;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "local label `%top` can only be used inside a macro",
+                "label `shared` is defined multiple times",
+                "label `%missing` is not defined",
+            ]
+        );
+        // The duplicate is reported at the second expansion, pointing back at the first
+        assert_eq!(&input[errors[1].labels[0].span.clone()], "$global_label");
+        assert_eq!(errors[1].labels[1].message, "previously defined here");
+    }
+
+    #[test]
+    fn recursive_macros_are_rejected() {
+        let input = "
+            .macro a {
+                add 1 2 3;
+                $b;
+            };
+
+            .macro b {
+                $a;
+            };
+
+            .macro c {
+                $c;
+            };
+
+            $a;
+            $c;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "macro `$a` invokes itself recursively",
+                "macro `$c` invokes itself recursively",
+            ]
+        );
+        assert_eq!(errors[0].notes, vec!["expansion cycle: $a -> $b -> $a"]);
+        assert_eq!(errors[1].notes, vec!["expansion cycle: $c -> $c"]);
+    }
+
+    #[test]
+    fn macro_expansion_depth_is_limited() {
+        let input = "
+            .macro one { add 1 2 3; };
+            .macro two { $one; };
+            .macro three { $two; };
+
+            $three;
+        "
+        .to_owned();
+
+        let options = CompileOptions {
+            max_expansion_depth: 2,
+            ..Default::default()
+        };
+        let errors = compile_asm_with_options(input.clone(), &options).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "macro expansion exceeds the maximum depth of 2"
+        );
+        assert_eq!(errors[0].notes, vec!["expanded through $three -> $two"]);
+
+        assert!(compile_asm(input).is_ok());
+    }
+
+    #[test]
+    fn include_cycles_are_rejected() {
+        let path = "./test_programs/cycle_a.avm".to_owned();
+        let errors = compile_file(&path).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, "./test_programs/cycle_b.avm");
+        assert_eq!(
+            errors[0].message,
+            "including `cycle_a.avm` would create a cycle"
+        );
+        assert_eq!(
+            errors[0].notes,
+            vec!["include cycle: ./test_programs/cycle_a.avm -> ./test_programs/cycle_b.avm -> ./test_programs/cycle_a.avm"]
+        );
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        // The same file is included via two different paths, but only compiled once
+        let bytecode = compile_file(&"./test_programs/includes_nested.avm".to_owned()).unwrap();
+
+        let expected_instructions = vec![
+            Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
+            Instruction::new(Opcode::ADD, false, vec![0.into(), 1.into(), 2.into()]),
+            Instruction::new(Opcode::ADD, false, vec![0.into(), 1.into(), 2.into()]),
+        ];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn includes_fall_back_to_include_paths() {
+        let path = "./test_programs/includes_library.avm".to_owned();
+
+        let errors = compile_file(&path).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "could not find included file `library.avm`"
+        );
+        assert_eq!(
+            errors[0].notes,
+            vec!["searched `./test_programs/library.avm`"]
+        );
+
+        let options = CompileOptions {
+            include_paths: vec![PathBuf::from("./test_programs/lib")],
+            ..Default::default()
+        };
+        let bytecode = compile_file_with_options(&path, &options).unwrap();
+        let expected_instructions = vec![Instruction::new(
+            Opcode::ADD,
+            false,
+            vec![0.into(), 1.into(), 2.into()],
+        )];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn source_includes_are_read_from_disk() {
+        let input = ".include \"test_programs/lib/library.avm\";\n$from_library;".to_owned();
+        let expected_instructions = vec![Instruction::new(
+            Opcode::ADD,
+            false,
+            vec![0.into(), 1.into(), 2.into()],
+        )];
+        let expected = generate_code(expected_instructions, Isa::V1).unwrap();
+
+        assert_eq!(compile_asm(input.clone()).unwrap(), expected);
+        assert_eq!(
+            compile_asm_as(input, &Default::default(), OutputFormat::Hex).unwrap(),
+            expected.as_bytes()
+        );
+    }
+
+    #[test]
+    fn namespaced_includes() {
+        let bytecode = compile_file(&"./test_programs/namespaces.avm".to_owned()).unwrap();
+
+        let expected_instructions = vec![
+            // $math::add_u64(1)
+            Instruction::new(Opcode::ADD, false, vec![1.into(), 0x40.into(), 1.into()]),
+            // $add_u64(1), the global macro of the same name
+            Instruction::new(Opcode::SUB, false, vec![1.into(), 2.into(), 3.into()]),
+            // $math::double(2), which refers to math's own helper and constant
+            Instruction::new(Opcode::ADD, false, vec![2.into(), 0x40.into(), 2.into()]),
+            Instruction::new(Opcode::ADD, false, vec![2.into(), 2.into(), 2.into()]),
+            // $other::add_u64(3)
+            Instruction::new(Opcode::MUL, false, vec![3.into(), 3.into(), 3.into()]),
+            // $math::bits
+            Instruction::new(Opcode::ADD, false, vec![0x40.into(), 9.into(), 9.into()]),
+        ];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn duplicate_definitions_are_rejected() {
+        let input = "
+            .const c = 1;
+            .const c = 2;
+            .macro m { add 1 2 3; };
+            .macro m { sub 1 2 3; };
+            $m;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "constant `$c` is defined multiple times",
+                "macro `$m` is defined multiple times",
+            ]
+        );
+        assert_eq!(
+            &input[errors[1].labels[0].span.clone()],
+            ".macro m { add 1 2 3; }"
+        );
+    }
+
     #[test]
     fn tagged_opcodes() {
         let inputs = "
@@ -325,7 +1899,7 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(inputs);
+        let bytecode = compile_asm(inputs).unwrap();
         let expected_bytecode = "0E000100000000000000020000000000000003";
         assert_eq!(bytecode, expected_bytecode);
     }
@@ -337,7 +1911,7 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(inputs);
+        let bytecode = compile_asm(inputs).unwrap();
         let expected_bytecode = "0E000100000000000000020000000000000003";
         assert_eq!(bytecode, expected_bytecode);
     }
@@ -349,7 +1923,7 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(inputs);
+        let bytecode = compile_asm(inputs).unwrap();
         let expected_bytecode = "24000112340000000000000002";
         assert_eq!(bytecode, expected_bytecode);
     }
@@ -361,7 +1935,7 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(inputs);
+        let bytecode = compile_asm(inputs).unwrap();
         let expected_bytecode = "24000530644E72E131A029B85045B68181585D97816A916871CA8D3C208C16D87CFD460000000000000002";
         assert_eq!(bytecode, expected_bytecode);
     }
@@ -374,21 +1948,622 @@ fn temporary_to_instruction_vector(parsed: Vec<Statement>) -> Vec<Instruction> {
         "
         .to_owned();
 
-        let bytecode = compile_asm(inputs);
+        let bytecode = compile_asm(inputs).unwrap();
         let expected_bytecode = "0000000000000000123400000000000012340000000000001234";
         assert_eq!(bytecode, expected_bytecode);
-}
+    }
 
+    #[test]
+    fn test_includes_io() {
+        // Test includes IO
+        let input = std::fs::read_to_string("./test_programs/includes.avm").unwrap();
+        let parsed = parse_asm(&input, "./test_programs/includes.avm").unwrap();
+
+        // Each file contains just one macro, so we expect that they end up pointing at the same thing
+        let expected_length = 2;
+        assert_eq!(parsed.len(), expected_length);
+    }
 
-#[test]
-fn test_includes_io() {
-    // Test includes IO
-    let input = std::fs::read_to_string("./test_programs/includes.avm").unwrap();
-    let parsed = parse_asm(input);
+    #[test]
+    fn undefined_macros_and_labels_are_all_reported() {
+        let input = "
+            $missing;
+            jump @nowhere;
+            add 1 2 3;
+            jumpi @elsewhere 1;
+        "
+        .to_owned();
 
-    // Each file contains just one macro, so we expect that they end up pointing at the same thing
-    let expected_length = 2;
-    assert_eq!(parsed.len(), expected_length);
+        let errors = compile_asm(input).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "macro `$missing` is not defined",
+                "label `nowhere` is not defined",
+                "label `elsewhere` is not defined",
+            ]
+        );
+        assert!(errors.iter().all(|e| e.is_error() && e.file == "<input>"));
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_offending_source() {
+        let input = "
+            add 1 2 3;
+            jump @nowhere;
+            $missing;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        assert_eq!(&input[errors[0].span.clone()], "$missing");
+        assert_eq!(&input[errors[1].span.clone()], "@nowhere");
+    }
+
+    #[test]
+    fn errors_inside_macros_report_the_invocation_site() {
+        let input = "
+            .macro inner {
+                set u8 0x1234 2;
+            };
+
+            .macro outer {
+                $inner;
+            };
+
+            $outer;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        // The error itself points into the macro definition
+        assert_eq!(&input[errors[0].span.clone()], "0x1234");
+
+        // Followed by each invocation, innermost first
+        let invocations: Vec<&str> = errors[0]
+            .labels
+            .iter()
+            .map(|label| &input[label.span.clone()])
+            .collect();
+        assert_eq!(invocations, vec!["$inner", "$outer"]);
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let inputs = "
+        .const BASE = 0x40;
+        .const SCRATCH = $BASE + 32;
+        .macro offset(a) {
+            add $a * 2 $a % 3 ($a - 1) << 4;
+        };
+        add $SCRATCH $BASE >> 2 | 1 0xff & 15;
+        $offset(5);
+        set u8 $BASE * 2 + 127 1;
+        "
+        .to_owned();
+
+        let bytecode = compile_asm(inputs).unwrap();
+        let expected_instructions = vec![
+            Instruction::new(Opcode::ADD, false, vec![96.into(), 17.into(), 15.into()]),
+            Instruction::new(Opcode::ADD, false, vec![10.into(), 2.into(), 64.into()]),
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![
+                    Operand::Tag(TypeTag::U8),
+                    "0xff".to_owned().into(),
+                    1.into(),
+                ],
+            ),
+        ];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn constants_can_be_defined_in_any_order() {
+        let inputs = "
+        .const SCRATCH = $BASE + $WORD;
+        add $SCRATCH $BASE $WORD;
+        .const BASE = 0x40;
+        .const WORD = 32;
+        "
+        .to_owned();
+
+        let bytecode = compile_asm(inputs).unwrap();
+        let expected_instructions = vec![Instruction::new(
+            Opcode::ADD,
+            false,
+            vec![96.into(), 64.into(), 32.into()],
+        )];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn constants_inside_macros() {
+        let inputs = "
+        .const width = 8;
+        .const offset = 2;
+        .macro shift(offset) {
+            add $offset $width $offset * $width;
+        };
+        .macro outer {
+            $shift($offset + 1);
+        };
+        $outer;
+        $shift($width);
+        "
+        .to_owned();
+
+        let bytecode = compile_asm(inputs).unwrap();
+        // The `offset` parameter shadows the constant of the same name
+        let expected_instructions = vec![
+            Instruction::new(Opcode::ADD, false, vec![3.into(), 8.into(), 24.into()]),
+            Instruction::new(Opcode::ADD, false, vec![8.into(), 8.into(), 64.into()]),
+        ];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn big_program() {
+        let bytecode = compile_file(&"./test_programs/big.avm".to_owned()).unwrap();
+
+        let ff = |value: &str| value.to_owned().into();
+        let expected_instructions = vec![
+            // admin:
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::FF), ff("0x0"), 11.into()],
+            ),
+            Instruction::new(Opcode::SLOAD, false, vec![11.into(), 1.into()]),
+            Instruction::new(Opcode::RETURN, false, vec![1.into(), 1.into()]),
+            // update_admin:
+            Instruction::new(Opcode::SENDER, false, vec![0.into()]),
+            Instruction::new(Opcode::SSTORE, false, vec![0.into(), 0.into()]),
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::U8), 1.into(), 1.into()],
+            ),
+            Instruction::new(Opcode::RETURN, false, vec![1.into(), 1.into()]),
+            // mint:
+            Instruction::new(
+                Opcode::CALLDATACOPY,
+                false,
+                vec![0.into(), 2.into(), 0.into()],
+            ),
+            Instruction::new(Opcode::MOV, false, vec![0.into(), 10.into()]),
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::FF), ff("0x1"), 11.into()],
+            ),
+            Instruction::new(Opcode::POSEIDON2, false, vec![10.into(), 10.into()]),
+            Instruction::new(Opcode::MOV, false, vec![10.into(), 0.into()]),
+            Instruction::new(Opcode::SLOAD, false, vec![0.into(), 42.into()]),
+            Instruction::new(Opcode::ADD, false, vec![42.into(), 1.into(), 43.into()]),
+            Instruction::new(Opcode::SSTORE, false, vec![43.into(), 0.into()]),
+            Instruction::new(Opcode::RETURN, false, vec![20.into(), 0.into()]),
+        ];
+        assert_eq!(
+            bytecode,
+            generate_code(expected_instructions, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn contracts_compile_each_entry_point_separately() {
+        let artifact = compile_contract(
+            &"./test_programs/big.avm".to_owned(),
+            &Default::default(),
+            &FsLoader,
+        )
+        .unwrap();
+        assert_eq!(artifact.name, "big");
+        let names: Vec<_> = artifact.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["admin", "update_admin", "mint"]);
+
+        let ff = |value: &str| value.to_owned().into();
+        let admin = vec![
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::FF), ff("0x0"), 11.into()],
+            ),
+            Instruction::new(Opcode::SLOAD, false, vec![11.into(), 1.into()]),
+            Instruction::new(Opcode::RETURN, false, vec![1.into(), 1.into()]),
+        ];
+        assert_eq!(
+            bytes_to_hex_string(&artifact.functions[0].bytecode),
+            generate_code(admin, Isa::V1).unwrap()
+        );
+
+        // Labels that are jumped to stay within the function, and resolve relative to its start
+        let artifact = compile_contract(
+            &"./test_programs/contract.avm".to_owned(),
+            &Default::default(),
+            &FsLoader,
+        )
+        .unwrap();
+        let names: Vec<_> = artifact.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["count", "one"]);
+        let count = vec![
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::U32), 0.into(), 0.into()],
+            ),
+            Instruction::new(
+                Opcode::SET,
+                false,
+                vec![Operand::Tag(TypeTag::U32), 1.into(), 1.into()],
+            ),
+            Instruction::new(Opcode::ADD, false, vec![0.into(), 1.into(), 0.into()]),
+            Instruction::new(Opcode::JUMPI, false, vec![2.into(), 0.into()]),
+            Instruction::new(Opcode::RETURN, false, vec![0.into(), 1.into()]),
+        ];
+        assert_eq!(
+            bytes_to_hex_string(&artifact.functions[0].bytecode),
+            generate_code(count, Isa::V1).unwrap()
+        );
+        let one = vec![Instruction::new(
+            Opcode::RETURN,
+            false,
+            vec![1.into(), 1.into()],
+        )];
+        assert_eq!(
+            bytes_to_hex_string(&artifact.functions[1].bytecode),
+            generate_code(one, Isa::V1).unwrap()
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&artifact.to_json()).unwrap();
+        assert_eq!(
+            json["functions"][1],
+            serde_json::json!({
+                "name": "one",
+                "is_unconstrained": true,
+                "custom_attributes": ["public"],
+                "abi": { "parameters": [], "return_type": null, "error_types": {} },
+                "bytecode": "NQAAAAAAAAAAAQAAAAAAAAAB",
+                "selector": "0x901717d1",
+            })
+        );
+        assert_eq!(json["name"], "contract");
+        assert_eq!(json["transpiled"], true);
+
+        // Contracts can be compiled from files that are not on disk
+        let loader = MemoryLoader::new()
+            .with_file("token.avm", ".include \"lib.avm\";\nmint:\n    $done;")
+            .with_file("lib.avm", ".macro done {\n    return 0 1;\n};");
+        let artifact =
+            compile_contract(&"token.avm".to_owned(), &Default::default(), &loader).unwrap();
+        assert_eq!(artifact.name, "token");
+        let done = vec![Instruction::new(
+            Opcode::RETURN,
+            false,
+            vec![0.into(), 1.into()],
+        )];
+        assert_eq!(
+            bytes_to_hex_string(&artifact.functions[0].bytecode),
+            generate_code(done, Isa::V1).unwrap()
+        );
+    }
+
+    #[test]
+    fn contract_errors() {
+        let errors = compile_contract(
+            &"./test_programs/contract_jumps.avm".to_owned(),
+            &Default::default(),
+            &FsLoader,
+        )
+        .unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|error| error.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["label `loop` belongs to function `count`, jumps cannot leave `escape`"]
+        );
+
+        let errors = compile_contract(
+            &"./test_programs/minimal.avm".to_owned(),
+            &Default::default(),
+            &FsLoader,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "instructions must follow an entry point when compiling a contract"
+        );
+    }
+
+    #[test]
+    fn entry_points_are_dispatched_by_selector() {
+        use crate::{host::InMemoryHost, interpreter::execute};
+        use num_bigint::BigUint;
+
+        let run = |bytecode: &str, isa: Isa, calldata: Vec<BigUint>| {
+            let mut host = InMemoryHost {
+                calldata,
+                storage: [(7u32.into(), 42u32.into())].into(),
+                ..Default::default()
+            };
+            let outcome = execute(bytecode, isa, &mut host).unwrap();
+            (outcome, host)
+        };
+        let get = BigUint::from(selector("get(Field)"));
+        let set = BigUint::from(selector("set(Field,Field)"));
+
+        let path = "./test_programs/entry_points.avm".to_owned();
+        let bytecode = compile_file(&path).unwrap();
+        let (outcome, _) = run(&bytecode, Isa::V1, vec![get, 7u32.into()]);
+        assert!(!outcome.reverted);
+        assert_eq!(outcome.output, [BigUint::from(42u32)]);
+        let (outcome, host) = run(&bytecode, Isa::V1, vec![set, 8u32.into(), 9u32.into()]);
+        assert!(!outcome.reverted);
+        assert_eq!(host.storage[&8u32.into()], 9u32.into());
+
+        // Calls that match no entry point revert, whichever way the profile copies calldata
+        let input = "
+            .entry one();
+            .entry two();
+        one:
+            set u8 1 0;
+            return 0 1;
+        two:
+            set u8 2 0;
+            return 0 1;
+        ";
+        for isa in [Isa::V1, Isa::Fixture] {
+            let options = CompileOptions {
+                isa,
+                ..Default::default()
+            };
+            let bytecode = compile_asm_with_options(input.to_owned(), &options).unwrap();
+            let call = |signature: &str| run(&bytecode, isa, vec![selector(signature).into()]).0;
+
+            assert_eq!(call("one()").output, [BigUint::from(1u32)]);
+            assert_eq!(call("two()").output, [BigUint::from(2u32)]);
+            assert!(call("three()").reverted);
+        }
+
+        // Contracts select their functions by the declared signatures instead
+        let artifact = compile_contract(&path, &Default::default(), &FsLoader).unwrap();
+        let selectors: Vec<_> = artifact.functions.iter().map(|f| f.selector).collect();
+        assert_eq!(
+            selectors,
+            [selector("get(Field)"), selector("set(Field,Field)")]
+        );
+        assert_eq!(artifact.functions[1].abi.parameters.len(), 2);
+
+        // Types the ABI cannot describe are reported at the entry point declaring them
+        let loader =
+            MemoryLoader::new().with_file("point.avm", ".entry get(Point);\nget:\n    return 0 0;");
+        let errors =
+            compile_contract(&"point.avm".to_owned(), &Default::default(), &loader).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "type `Point` cannot be described in a contract ABI"
+        );
+    }
+
+    #[test]
+    fn entry_point_errors() {
+        let input = "
+            .entry a();
+            .entry a(Field);
+            .entry b();
+            jump @b;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input).unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|error| error.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "entry point `a` is declared multiple times",
+                "entry point `a` has no matching label",
+                "entry point `b` has no matching label",
+                "label `b` is not defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_errors() {
+        let input = "
+            .const a = $b + 1;
+            .const b = $a;
+            .const c = $c;
+            .const d = $missing * 2;
+            add $a $d 1;
+            add $nowhere 1 2;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "constant `$a` is defined in terms of itself",
+                "constant `$c` is defined in terms of itself",
+                "constant `$missing` is not defined",
+                "constant `$nowhere` is not defined",
+            ]
+        );
+        assert_eq!(errors[0].notes, vec!["reference cycle: $a -> $b -> $a"]);
+        assert_eq!(errors[1].notes, vec!["reference cycle: $c -> $c"]);
+        assert_eq!(&input[errors[2].span.clone()], "$missing * 2");
+        assert_eq!(&input[errors[3].span.clone()], "$nowhere");
+    }
+
+    #[test]
+    fn expression_errors() {
+        let input = "
+            set u8 255 + 1 2;
+            add 0xffffffffffffffff + 1 1 2;
+            add 1 - 2 1 2;
+            add 1 / 0 1 2;
+            add u8 + 1 1 2;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "value `256` does not fit in 8 bits",
+                "value `18446744073709551616` does not fit in 64 bits",
+                "`1 - 2` underflows",
+                "`1 / 0` divides by zero",
+                "type tags cannot be used in expressions",
+            ]
+        );
+        assert_eq!(&input[errors[0].span.clone()], "255 + 1");
+    }
+
+    #[test]
+    fn operands_are_checked_against_the_opcode_spec() {
+        let input = "
+        start:
+            add 1 2;
+            jump 1 2 3;
+            jump! 1;
+            add u8 1 2;
+            set u8 256 1;
+            cast 9 1 2;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input.clone()).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "ADD expects 3 operands, but 2 were given",
+                "JUMP expects 1 operand, but 3 were given",
+                "JUMP does not support indirect addressing",
+                "operand 1 of ADD must be a memory offset",
+                "value `256` does not fit in 8 bits",
+                "invalid type tag `9`",
+            ]
+        );
+        assert_eq!(&input[errors[3].span.clone()], "u8");
+        assert_eq!(&input[errors[4].span.clone()], "256");
+
+        let input = "start: add @start 1 2;".to_owned();
+        let errors = compile_asm(input.clone()).unwrap_err();
+        assert_eq!(errors[0].message, "ADD does not take a label");
+        assert_eq!(&input[errors[0].span.clone()], "@start");
+    }
+
+    #[test]
+    fn output_formats() {
+        let input = "set u8 0xab 0; jumpi 0 0;".to_owned();
+        let options = CompileOptions::default();
+        let hex = compile_asm_with_options(input.clone(), &options).unwrap();
+        let compile = |format| compile_asm_as(input.clone(), &options, format).unwrap();
+
+        assert_eq!(
+            hex,
+            "240000AB0000000000000000210000000000000000000000000000000000"
+        );
+        assert_eq!(compile(OutputFormat::Hex), hex.as_bytes());
+        assert_eq!(compile(OutputFormat::Binary), hex::decode(&hex).unwrap());
+        assert_eq!(
+            compile(OutputFormat::PrefixedHex),
+            format!("0x{}", hex.to_lowercase()).as_bytes()
+        );
+    }
+
+    #[test]
+    fn instruction_set_profiles() {
+        let input = "
+        start:
+            add 1 2 3;
+            set u16 0x1234 2;
+            jump @start;
+        "
+        .to_owned();
+        let fixture = CompileOptions {
+            isa: Isa::Fixture,
+            ..Default::default()
+        };
+
+        // Opcodes are renumbered and operands are 4 bytes wide
+        let bytecode = compile_asm_with_options(input.clone(), &fixture).unwrap();
+        assert_eq!(
+            bytecode,
+            "0000000000010000000200000003250001123400000002210000000000"
+        );
+
+        assert_ne!(compile_asm(input).unwrap(), bytecode);
+
+        // Opcodes a profile drops are an error
+        assert!(compile_asm("sha256 1 2 3;".to_owned()).is_ok());
+        let errors = compile_asm_with_options("sha256 1 2 3;".to_owned(), &fixture).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "SHA256 is not part of the fixture instruction set"
+        );
+    }
+
+    #[test]
+    fn malformed_tagged_opcode_is_an_error() {
+        let input = "
+            set u8 0x1234 2;
+            cast;
+        "
+        .to_owned();
+
+        let errors = compile_asm(input).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].message,
+            "hex literal `0x1234` does not fit in 8 bits"
+        );
+        assert_eq!(
+            errors[1].message,
+            "CAST expects 3 operands, but 0 were given"
+        );
+
+        // Only the value counts towards the width, not leading zeros
+        let bytecode = compile_asm("set u8 0x00ff 0;".to_owned()).unwrap();
+        assert_eq!(bytecode, compile_asm("set u8 255 0;".to_owned()).unwrap());
+        let errors = compile_asm("set u8 0x0100 0;".to_owned()).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "hex literal `0x0100` does not fit in 8 bits"
+        );
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        let path = "./test_programs/missing_include.avm".to_owned();
+        let errors = compile_file(&path).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, path);
+        assert_eq!(errors[0].span, 0..29);
+        assert_eq!(
+            errors[0].message,
+            "could not find included file `does_not_exist.avm`"
+        );
+    }
 }
 
-// Next test: make labels work in the multi file setting
\ No newline at end of file
+// Next test: make labels work in the multi file setting
@@ -0,0 +1,452 @@
+// An interactive debugger for assembled programs
+//
+// Programs are compiled with the span of every instruction kept, so each program counter maps
+// back to the line it was written on and to each macro invocation it was expanded through.
+// Breakpoints can be placed on any of those lines, or on a label.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    compiler::{assemble_file, display_label, Assembly, CompileOptions},
+    diagnostic::Diagnostic,
+    host::Host,
+    interpreter::{ExecutionError, Machine, Outcome},
+    loader::FsLoader,
+    source_map::{SourceLocation, Sources},
+};
+
+const HELP: &str = "\
+commands:
+    break <line>, break <file>:<line>, break @<label>    set a breakpoint
+    delete <n>                                            remove breakpoint n
+    step                                                  run to the next source line, entering macros
+    next                                                  run to the next line of the program, stepping over macros and calls
+    stepi                                                 run a single instruction
+    continue                                              run to the next breakpoint
+    memory <offset> [count]                               show memory cells and their tags
+    stack                                                 show the internal call stack
+    gas                                                   show the gas remaining
+    where                                                 show the current position
+    quit                                                  stop debugging";
+
+/// Debug the program at `path`, reading commands from `input` and writing to `output`.
+///
+/// Returns the program's diagnostics if it does not compile.
+pub fn debug(
+    path: &String,
+    options: &CompileOptions,
+    host: &mut dyn Host,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), Vec<Diagnostic>> {
+    let assembly = assemble_file(path, options, &FsLoader)?;
+    let mut debugger = Debugger::new(assembly, path, options, host);
+
+    let _ = writeln!(output, "{}", debugger.position());
+    let mut lines = input.lines();
+    loop {
+        let _ = write!(output, "(avm) ");
+        let _ = output.flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        let line = line.trim();
+        if matches!(line, "quit" | "q") {
+            break;
+        }
+        if !line.is_empty() {
+            let _ = writeln!(output, "{}", debugger.command(line));
+        }
+    }
+
+    Ok(())
+}
+
+struct Breakpoint {
+    description: String,
+    pcs: BTreeSet<usize>,
+}
+
+struct Debugger<'a> {
+    machine: Machine<'a>,
+    // Where each instruction was written, followed by each macro invocation it was expanded
+    // through, innermost first
+    locations: Vec<Vec<SourceLocation>>,
+    labels: HashMap<String, u64>,
+    sources: Sources,
+    root: String,
+    // Removed breakpoints leave a gap, so that the rest keep their numbers
+    breakpoints: Vec<Option<Breakpoint>>,
+    finished: bool,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(
+        assembly: Assembly,
+        path: &str,
+        options: &CompileOptions,
+        host: &'a mut dyn Host,
+    ) -> Self {
+        let mut sources = Sources::default();
+        let locations = assembly
+            .spans
+            .iter()
+            .map(|span| {
+                std::iter::once(span)
+                    .chain(span.expansions())
+                    .map(|span| sources.locate(span))
+                    .collect()
+            })
+            .collect();
+
+        Debugger {
+            machine: Machine::new(assembly.instructions, options.isa, host),
+            locations,
+            labels: assembly.labels,
+            sources,
+            root: path.to_owned(),
+            breakpoints: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arguments: Vec<&str> = words.collect();
+
+        match (command, arguments.as_slice()) {
+            ("break" | "b", [location]) => self.set_breakpoint(location),
+            ("delete" | "d", [number]) => self.delete_breakpoint(number),
+            ("step" | "s", []) => self.step_line(false),
+            ("next" | "n", []) => self.step_line(true),
+            ("stepi" | "si", []) => self.run_until(|_| true),
+            ("continue" | "c", []) => self.run_until(|_| false),
+            ("memory" | "x", [offset]) => self.memory(offset, "1"),
+            ("memory" | "x", [offset, count]) => self.memory(offset, count),
+            ("stack" | "bt", []) => self.stack(),
+            ("gas", []) => format!(
+                "l2 gas: {}, da gas: {}",
+                self.machine.gas.l2, self.machine.gas.da
+            ),
+            ("where" | "w", []) if self.finished => "the program has finished".to_owned(),
+            ("where" | "w", []) => self.position(),
+            ("help" | "h", []) => HELP.to_owned(),
+            _ => format!("unknown command `{line}`, type `help` for a list of commands"),
+        }
+    }
+
+    fn set_breakpoint(&mut self, location: &str) -> String {
+        let pcs: BTreeSet<usize> = match location.strip_prefix('@') {
+            Some(name) => self
+                .labels
+                .iter()
+                .filter(|(label, _)| display_label(label) == name)
+                .map(|(_, &pc)| pc as usize)
+                .collect(),
+            None => {
+                let (file, line) = match location.rsplit_once(':') {
+                    Some((file, line)) => (file, line),
+                    None => (self.root.as_str(), location),
+                };
+                let Ok(line) = line.parse::<usize>() else {
+                    return format!("`{location}` is not a line or label");
+                };
+                (0..self.locations.len())
+                    .filter(|&pc| {
+                        self.locations[pc].iter().any(|candidate| {
+                            candidate.line == line
+                                && Path::new(&candidate.file).ends_with(Path::new(file))
+                        })
+                    })
+                    .collect()
+            }
+        };
+
+        if pcs.is_empty() {
+            return format!("no instructions at `{location}`");
+        }
+
+        self.breakpoints.push(Some(Breakpoint {
+            description: location.to_owned(),
+            pcs,
+        }));
+        format!("breakpoint {} at {location}", self.breakpoints.len())
+    }
+
+    fn delete_breakpoint(&mut self, number: &str) -> String {
+        let slot = number
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| self.breakpoints.get_mut(number.checked_sub(1)?));
+        match slot.and_then(Option::take) {
+            Some(breakpoint) => {
+                format!("deleted breakpoint {number} at {}", breakpoint.description)
+            }
+            None => format!("there is no breakpoint {number}"),
+        }
+    }
+
+    // Run until the current line changes. Stepping into a macro counts each expansion of a line
+    // as a line of its own, while stepping over only looks at the line in the program itself and
+    // waits for internal calls to return
+    fn step_line(&mut self, over: bool) -> String {
+        let line = |debugger: &Debugger, pc: usize| {
+            let locations = debugger.locations.get(pc)?;
+            let locations = match over {
+                true => &locations[locations.len() - 1..],
+                false => &locations[..],
+            };
+            let lines: Vec<_> = locations
+                .iter()
+                .map(|location| (location.file.clone(), location.line))
+                .collect();
+            Some(lines)
+        };
+        let start = line(self, self.machine.pc);
+        let depth = self.machine.call_stack.len();
+
+        self.run_until(|debugger| {
+            let pc = debugger.machine.pc;
+            line(debugger, pc) != start && (!over || debugger.machine.call_stack.len() <= depth)
+        })
+    }
+
+    // Execute instructions until `stop` returns true, a breakpoint is reached or the program
+    // finishes. At least one instruction is always executed
+    fn run_until(&mut self, stop: impl Fn(&Debugger) -> bool) -> String {
+        if self.finished {
+            return "the program has finished".to_owned();
+        }
+
+        loop {
+            match self.machine.step() {
+                Ok(Some(Outcome { reverted, output })) => {
+                    self.finished = true;
+                    let output = output
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return match reverted {
+                        false => format!("the program returned [{output}]"),
+                        true => format!("the program reverted with [{output}]"),
+                    };
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    self.finished = true;
+                    return match error {
+                        ExecutionError::Fault { .. } => {
+                            format!("error: {error}\n{}", self.position())
+                        }
+                        ExecutionError::Decode(_) => format!("error: {error}"),
+                    };
+                }
+            }
+
+            let pc = self.machine.pc;
+            let hit = self.breakpoints.iter().position(|breakpoint| {
+                breakpoint
+                    .as_ref()
+                    .is_some_and(|breakpoint| breakpoint.pcs.contains(&pc))
+            });
+            if let Some(number) = hit {
+                return format!("breakpoint {} hit\n{}", number + 1, self.position());
+            }
+            if stop(self) {
+                return self.position();
+            }
+        }
+    }
+
+    fn memory(&self, offset: &str, count: &str) -> String {
+        let (Ok(offset), Ok(count)) = (offset.parse::<u64>(), count.parse::<u64>()) else {
+            return "usage: memory <offset> [count]".to_owned();
+        };
+
+        (offset..offset.saturating_add(count))
+            .map(|offset| {
+                let written = self.machine.memory.contains_key(&offset);
+                format!(
+                    "{offset}: {}{}",
+                    self.machine.read(offset),
+                    if written { "" } else { " (unset)" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The current instruction, followed by each `INTERNALCALL` that has not yet returned
+    fn stack(&self) -> String {
+        let calls = self
+            .machine
+            .call_stack
+            .iter()
+            .rev()
+            .map(|&return_to| return_to - 1);
+        std::iter::once(self.machine.pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(depth, pc)| match self.locations.get(pc) {
+                Some(locations) => format!("#{depth} instruction {pc} at {}", locations[0]),
+                None => format!("#{depth} the end of the program"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The instruction about to run, along with its source and any macros it came from
+    fn position(&self) -> String {
+        let pc = self.machine.pc;
+        let Some(locations) = self.locations.get(pc) else {
+            return format!("{pc}: the end of the program");
+        };
+
+        let mut out = format!("{pc}: {} {}", locations[0], self.source(&locations[0]));
+        for invocation in &locations[1..] {
+            out.push_str(&format!(
+                "\n    in the macro invocation at {invocation} {}",
+                self.source(invocation)
+            ));
+        }
+        out
+    }
+
+    fn source(&self, location: &SourceLocation) -> &str {
+        self.sources.line(location).map_or("", str::trim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{host::InMemoryHost, interpreter::Gas};
+
+    // Run a debugging session, returning everything it printed
+    fn session(commands: &str) -> String {
+        let mut output = Vec::new();
+        debug(
+            &"./test_programs/debug.avm".to_owned(),
+            &CompileOptions::default(),
+            &mut InMemoryHost::default(),
+            commands.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap().replace("(avm) ", "")
+    }
+
+    #[test]
+    fn breakpoints_on_lines_and_labels() {
+        let output = session(
+            "break 17
+break @double
+break 8
+continue
+memory 0 3
+delete 2
+continue
+stack
+continue
+continue
+",
+        );
+        assert_eq!(
+            output,
+            "0: ./test_programs/debug.avm:13:5 set u32 3 0;
+breakpoint 1 at 17
+breakpoint 2 at @double
+breakpoint 3 at 8
+breakpoint 1 hit
+3: ./test_programs/debug.avm:3:5 add $a $b $a;
+    in the macro invocation at ./test_programs/debug.avm:17:5 $accumulate(0, 1);
+0: u32 3
+1: u32 4
+2: u32 1
+deleted breakpoint 2 at @double
+breakpoint 3 hit
+7: ./test_programs/debug.avm:3:5 add $a $b $a;
+    in the macro invocation at ./test_programs/debug.avm:8:5 $accumulate($a, $b);
+    in the macro invocation at ./test_programs/debug.avm:21:5 $twice(0, 2);
+#0 instruction 7 at ./test_programs/debug.avm:3:5
+#1 instruction 4 at ./test_programs/debug.avm:18:5
+the program returned [9]
+the program has finished
+"
+        );
+    }
+
+    #[test]
+    fn stepping_follows_the_source() {
+        let output = session(
+            "next
+stepi
+next
+next
+step
+step
+next
+next
+memory 0
+gas
+",
+        );
+        let gas = Gas::default();
+        assert_eq!(
+            output,
+            format!(
+                "0: ./test_programs/debug.avm:13:5 set u32 3 0;
+1: ./test_programs/debug.avm:14:5 set u32 4 1;
+2: ./test_programs/debug.avm:15:5 set u32 1 2;
+3: ./test_programs/debug.avm:3:5 add $a $b $a;
+    in the macro invocation at ./test_programs/debug.avm:17:5 $accumulate(0, 1);
+4: ./test_programs/debug.avm:18:5 internalcall @double;
+6: ./test_programs/debug.avm:3:5 add $a $b $a;
+    in the macro invocation at ./test_programs/debug.avm:7:5 $accumulate($a, $b);
+    in the macro invocation at ./test_programs/debug.avm:21:5 $twice(0, 2);
+7: ./test_programs/debug.avm:3:5 add $a $b $a;
+    in the macro invocation at ./test_programs/debug.avm:8:5 $accumulate($a, $b);
+    in the macro invocation at ./test_programs/debug.avm:21:5 $twice(0, 2);
+8: ./test_programs/debug.avm:22:5 internalreturn;
+5: ./test_programs/debug.avm:19:5 return 0 1;
+0: u32 9
+l2 gas: {}, da gas: {}
+",
+                gas.l2 - 8,
+                gas.da
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let output = session(
+            "break 100
+break @nowhere
+break main.avm:x
+delete 1
+memory zero
+memory 50
+jump
+",
+        );
+        assert_eq!(
+            output,
+            "0: ./test_programs/debug.avm:13:5 set u32 3 0;
+no instructions at `100`
+no instructions at `@nowhere`
+`main.avm:x` is not a line or label
+there is no breakpoint 1
+usage: memory <offset> [count]
+50: ff 0 (unset)
+unknown command `jump`, type `help` for a list of commands
+"
+        );
+    }
+}
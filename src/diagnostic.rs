@@ -0,0 +1,109 @@
+use std::{fmt, ops::Range};
+
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A secondary location attached to a diagnostic, e.g. the site a macro was invoked from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub file: String,
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// A problem found while compiling, pointing at the byte range of the source that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub span: Range<usize>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(file: impl Into<String>, span: Range<usize>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            file: file.into(),
+            span,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    // An error at the given span, labelled with every macro invocation it was expanded through
+    pub(crate) fn error_at(span: &Span, message: impl Into<String>) -> Self {
+        let mut diagnostic = Diagnostic::error(&*span.file, span.range(), message);
+        for invocation in span.expansions() {
+            diagnostic = diagnostic.with_label(invocation, "in this macro invocation");
+        }
+        diagnostic
+    }
+
+    pub(crate) fn with_label(mut self, span: &Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            file: span.file.to_string(),
+            span: span.range(),
+            message: message.into(),
+        });
+        self
+    }
+
+    pub(crate) fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn warning(
+        file: impl Into<String>,
+        span: Range<usize>,
+        message: impl Into<String>,
+    ) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(file, span, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({}:{}..{})",
+            self.severity, self.message, self.file, self.span.start, self.span.end
+        )?;
+        for label in &self.labels {
+            write!(
+                f,
+                "\n  note: {} ({}:{}..{})",
+                label.message, label.file, label.span.start, label.span.end
+            )?;
+        }
+        for note in &self.notes {
+            write!(f, "\n  note: {note}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+// Turn bytecode back into assembly
+//
+// Each instruction is decoded using the opcode spec of the instruction set it was assembled for.
+// Jump targets are given labels so that the output reads like the source it came from, and
+// assembling it again produces the same bytes.
+
+use std::{collections::BTreeSet, fmt, fmt::Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+
+use crate::{
+    codegen::OutputFormat,
+    instruction::Instruction,
+    isa::{Isa, OperandKind, OperandWidth},
+    parser::{Operand, TypeTag},
+};
+
+/// Bytecode that could not be decoded, along with the byte offset the problem was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassemblyError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DisassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.message, self.offset)
+    }
+}
+
+/// Disassemble hex encoded bytecode, as produced by the compiler, into assembly.
+pub fn disassemble(bytecode: &str, isa: Isa) -> Result<String, DisassemblyError> {
+    disassemble_bytes(&decode_hex(bytecode)?, isa)
+}
+
+/// Disassemble raw bytecode into assembly.
+pub fn disassemble_bytes(bytecode: &[u8], isa: Isa) -> Result<String, DisassemblyError> {
+    let instructions = decode(bytecode, isa)?;
+    Ok(print(&instructions, isa))
+}
+
+/// Read bytecode written out in any of the output formats. Without a format, hex is assumed if the
+/// input is only hex digits, then base64 if it decodes as such, and otherwise the raw bytes.
+pub fn read_bytecode(
+    input: &[u8],
+    format: Option<OutputFormat>,
+) -> Result<Vec<u8>, DisassemblyError> {
+    let text = std::str::from_utf8(input);
+    let format = format.unwrap_or_else(|| match text {
+        Ok(text) if is_hex(text) => OutputFormat::Hex,
+        Ok(text) if STANDARD.decode(text.trim()).is_ok() => OutputFormat::Base64,
+        _ => OutputFormat::Binary,
+    });
+
+    let text = || {
+        text.map_err(|_| DisassemblyError {
+            offset: 0,
+            message: format!("bytecode is not {format} text"),
+        })
+    };
+    match format {
+        OutputFormat::Binary => Ok(input.to_vec()),
+        OutputFormat::Base64 => STANDARD
+            .decode(text()?.trim())
+            .map_err(|error| DisassemblyError {
+                offset: 0,
+                message: format!("bytecode is not valid base64: {error}"),
+            }),
+        OutputFormat::Hex | OutputFormat::LowercaseHex | OutputFormat::PrefixedHex => {
+            decode_hex(text()?)
+        }
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    text.trim()
+        .trim_start_matches("0x")
+        .chars()
+        .all(|c| c.is_ascii_hexdigit() || c.is_whitespace())
+}
+
+// Parse hex encoded bytecode, with or without a `0x` prefix
+pub(crate) fn decode_hex(bytecode: &str) -> Result<Vec<u8>, DisassemblyError> {
+    let hex: String = bytecode
+        .trim()
+        .trim_start_matches("0x")
+        .split_whitespace()
+        .collect();
+    hex::decode(&hex).map_err(|error| DisassemblyError {
+        offset: 0,
+        message: format!("bytecode is not valid hex: {error}"),
+    })
+}
+
+// Decode bytecode into the instructions it was assembled from
+pub(crate) fn decode(bytes: &[u8], isa: Isa) -> Result<Vec<Instruction>, DisassemblyError> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    // Take the next `length` bytes, failing if the bytecode ends part way through an instruction
+    let take = |offset: &mut usize, length: usize| {
+        let start = *offset;
+        let taken = bytes
+            .get(start..start + length)
+            .ok_or_else(|| DisassemblyError {
+                offset: start,
+                message: "bytecode ends part way through an instruction".to_owned(),
+            })?;
+        *offset += length;
+        Ok::<_, DisassemblyError>(taken)
+    };
+
+    while offset < bytes.len() {
+        let start = offset;
+        let value = take(&mut offset, 1)?[0];
+        let opcode = isa.opcode(value).ok_or_else(|| DisassemblyError {
+            offset: start,
+            message: format!("unknown opcode `{value:#04x}` in the {isa} instruction set"),
+        })?;
+        let spec = isa
+            .spec(opcode)
+            .expect("decoded opcodes are part of the instruction set");
+        let indirect = take(&mut offset, 1)?[0] != 0;
+
+        let mut operands = Vec::new();
+        let mut tag = None;
+        for operand_spec in spec.operands {
+            if operand_spec.kind == OperandKind::Tag {
+                let value = take(&mut offset, 1)?[0];
+                let decoded = TypeTag::try_from(value).map_err(|message| DisassemblyError {
+                    offset: offset - 1,
+                    message,
+                })?;
+                tag = Some(decoded.clone());
+                operands.push(Operand::Tag(decoded));
+                continue;
+            }
+
+            let width = match operand_spec.width {
+                OperandWidth::Bytes(bytes) => bytes,
+                OperandWidth::Tagged => {
+                    tag.as_ref().expect("tagged operands follow a tag").bits() / 8
+                }
+            };
+            let value = BigUint::from_bytes_be(take(&mut offset, width)?);
+            operands.push(to_operand(&value));
+        }
+
+        instructions.push(Instruction::new(opcode, indirect, operands));
+    }
+
+    Ok(instructions)
+}
+
+fn print(instructions: &[Instruction], isa: Isa) -> String {
+    let is_jump_target = |instruction: &Instruction, index: usize| {
+        isa.spec(instruction.opcode)
+            .is_some_and(|spec| spec.operands[index].kind == OperandKind::JumpTarget)
+    };
+
+    // Jump targets are instruction indices, only those within the program can be labelled
+    let mut targets = BTreeSet::new();
+    for instruction in instructions {
+        for (index, operand) in instruction.operands.iter().enumerate() {
+            if let (true, Operand::Decimal(target)) = (is_jump_target(instruction, index), operand)
+            {
+                if *target <= instructions.len() as u64 {
+                    targets.insert(*target);
+                }
+            }
+        }
+    }
+    let label = |target: u64| format!("label_{target}");
+
+    let mut out = String::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if targets.contains(&(index as u64)) {
+            let _ = writeln!(out, "{}:", label(index as u64));
+        }
+
+        let mut line = format!("    {}", instruction.opcode.name().to_lowercase());
+        if instruction.indirect {
+            line.push('!');
+        }
+        for (index, operand) in instruction.operands.iter().enumerate() {
+            let text = match operand {
+                // Labels cannot be combined with indirect addressing
+                Operand::Decimal(target)
+                    if is_jump_target(instruction, index)
+                        && targets.contains(target)
+                        && !instruction.indirect =>
+                {
+                    format!("@{}", label(*target))
+                }
+                Operand::Tag(tag) => tag.to_string(),
+                Operand::Decimal(value) => value.to_string(),
+                Operand::Hex(value) => value.clone(),
+                Operand::Variable(_) | Operand::Expression(..) => {
+                    unreachable!("decoded operands are literals")
+                }
+            };
+            line.push(' ');
+            line.push_str(&text);
+        }
+        let _ = writeln!(out, "{line};");
+    }
+
+    // A jump to the end of the program
+    if targets.contains(&(instructions.len() as u64)) {
+        let _ = writeln!(out, "{}:", label(instructions.len() as u64));
+    }
+
+    out
+}
+
+// Decimal literals only go up to 64 bits, anything larger is written in hex
+fn to_operand(value: &BigUint) -> Operand {
+    match value.to_u64() {
+        Some(value) => Operand::Decimal(value),
+        None => Operand::Hex(format!("0x{value:x}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile_asm_with_options, compile_file, CompileOptions};
+
+    #[test]
+    fn disassembles_instructions() {
+        let bytecode = compile_file(&"./test_programs/labels.avm".to_owned()).unwrap();
+
+        let expected = "    add 1 2 3;
+    jump @label_3;
+    sub 1 2 3;
+label_3:
+    add 1 2 3;
+";
+        assert_eq!(disassemble(&bytecode, Isa::V1).unwrap(), expected);
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let source = "
+        start:
+            add! 1 2 3;
+            cast u16 2 3;
+            set u8 255 4;
+            set ff 0x30644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd46 5;
+            jumpi @start 5;
+            jumpi! 2 5;
+            internalcall @end;
+            fdiv 1 2 3;
+        end:
+        ";
+
+        for isa in [Isa::V1, Isa::Fixture] {
+            let options = CompileOptions {
+                isa,
+                ..Default::default()
+            };
+            let bytecode = compile_asm_with_options(source.to_owned(), &options).unwrap();
+            let disassembled = disassemble(&bytecode, isa).unwrap();
+            let recompiled = compile_asm_with_options(disassembled, &options).unwrap();
+            assert_eq!(recompiled, bytecode, "round trip through {isa}");
+        }
+    }
+
+    #[test]
+    fn bytecode_is_read_in_every_output_format() {
+        let bytecode =
+            decode_hex(&compile_file(&"./test_programs/labels.avm".to_owned()).unwrap()).unwrap();
+
+        for format in OutputFormat::ALL {
+            let written = format.render(&bytecode);
+            for given in [Some(format), None] {
+                let read = read_bytecode(&written, given).unwrap();
+                assert_eq!(read, bytecode, "{format} read as {given:?}");
+                assert_eq!(
+                    disassemble_bytes(&read, Isa::V1).unwrap(),
+                    disassemble_bytes(&bytecode, Isa::V1).unwrap()
+                );
+            }
+        }
+
+        let error = read_bytecode(&[0xff], Some(OutputFormat::Base64)).unwrap_err();
+        assert_eq!(error.message, "bytecode is not base64 text");
+    }
+
+    #[test]
+    fn malformed_bytecode_is_an_error() {
+        let error = |bytecode: &str| disassemble(bytecode, Isa::V1).unwrap_err().to_string();
+
+        assert_eq!(
+            error("ff00"),
+            "unknown opcode `0xff` in the v1 instruction set (at byte 0)"
+        );
+        assert_eq!(
+            error("000000"),
+            "bytecode ends part way through an instruction (at byte 2)"
+        );
+        assert_eq!(error("240009"), "invalid type tag `9` (at byte 2)");
+        assert!(error("zz").starts_with("bytecode is not valid hex"));
+    }
+}
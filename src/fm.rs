@@ -1,52 +1,218 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     path::{Path, PathBuf},
 };
 
-use crate::parser::Statement;
+use crate::{
+    diagnostic::Diagnostic,
+    loader::FileLoader,
+    parser::Statement,
+    span::{Span, Spanned},
+};
+
+pub struct FileManager<'a> {
+    loader: &'a dyn FileLoader,
+    file_stack: VecDeque<PendingInclude>,
+    // Directories searched for includes that are not found next to the including file
+    include_paths: Vec<PathBuf>,
+    // Canonical paths of every file read so far, so that each file is only included once per
+    // namespace
+    seen: HashSet<(PathBuf, String)>,
+}
+
+// A file waiting to be read
+struct PendingInclude {
+    path: SourcePath,
+    // The include statement that requested it
+    span: Span,
+    // Every file that was included on the way to this one, starting from the root
+    include_chain: Vec<SourcePath>,
+    namespace: String,
+}
+
+#[derive(Clone)]
+pub struct SourcePath {
+    // Used to compare files, however they were referred to
+    canonical: PathBuf,
+    // Used when reporting on the file
+    pub name: String,
+}
 
-pub struct FileManager {
-    file_stack: VecDeque<String>,
+pub struct IncludedFile {
+    pub name: String,
+    pub contents: String,
+    // The files that led to this one being included, ending with the file itself
+    pub include_chain: Vec<SourcePath>,
+    // The namespace the file's macros and constants are defined in, empty for the global one
+    pub namespace: String,
 }
 
-impl FileManager {
-    pub fn new() -> Self {
+impl SourcePath {
+    pub fn new(path: &Path, loader: &dyn FileLoader) -> std::io::Result<Self> {
+        Ok(SourcePath {
+            canonical: loader.canonicalize(path)?,
+            name: path.display().to_string(),
+        })
+    }
+}
+
+impl<'a> FileManager<'a> {
+    pub fn new(include_paths: Vec<PathBuf>, loader: &'a dyn FileLoader) -> Self {
         Self {
+            loader,
             file_stack: VecDeque::new(),
+            include_paths,
+            seen: HashSet::new(),
         }
     }
 
-    pub fn get_next_file_contents(&mut self) -> String {
-        let file = self.file_stack.pop_front().unwrap();
-        // TODO( relative paths);
-        FileManager::read_file_contents(file)
+    // Register the file compilation starts from
+    pub fn add_root(&mut self, path: &Path) -> std::io::Result<SourcePath> {
+        let root = SourcePath::new(path, self.loader)?;
+        self.seen.insert((root.canonical.clone(), String::new()));
+        Ok(root)
+    }
+
+    // Register source that compilation starts from which was not read from a file, under the
+    // name given to it
+    pub fn add_source_root(&mut self, name: &str) -> SourcePath {
+        let root = SourcePath {
+            canonical: PathBuf::from(name),
+            name: name.to_owned(),
+        };
+        self.seen.insert((root.canonical.clone(), String::new()));
+        root
+    }
+
+    pub fn get_next_file_contents(&mut self) -> Result<IncludedFile, Diagnostic> {
+        let include = self.file_stack.pop_front().unwrap();
+        let contents = self
+            .read_file_contents(&include.path.name)
+            .map_err(|error| {
+                Diagnostic::error_at(
+                    &include.span,
+                    format!(
+                        "could not read included file `{}`: {error}",
+                        include.path.name
+                    ),
+                )
+            })?;
+
+        let mut include_chain = include.include_chain;
+        include_chain.push(include.path.clone());
+        Ok(IncludedFile {
+            name: include.path.name,
+            contents,
+            include_chain,
+            namespace: include.namespace,
+        })
     }
 
     pub fn is_empty(&self) -> bool {
         self.file_stack.is_empty()
     }
 
-    pub fn extend_file_stack(&mut self, parsed: &Vec<Statement>) {
-        let include_statements: Vec<Statement> = parsed
-            .iter()
-            .filter_map(|statement| {
-                if matches!(statement, Statement::IncludeStatement(_)) {
-                    Some(statement.clone())
-                } else {
-                    None
+    // Queue up the includes of a file.
+    // Files that have already been included are skipped, and any include that would include a
+    // file from within itself is an error.
+    // Included files share the namespace of the including file, unless included `as` another
+    pub fn extend_file_stack(
+        &mut self,
+        parsed: &[Spanned<Statement>],
+        include_chain: &[SourcePath],
+        namespace: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let current_file = &include_chain[include_chain.len() - 1];
+
+        for statement in parsed {
+            if let Statement::IncludeStatement(include_path, alias) = &statement.node {
+                let Some(path) = self.find_include(Path::new(&current_file.name), include_path)
+                else {
+                    let searched = self
+                        .search_locations(Path::new(&current_file.name), include_path)
+                        .map(|candidate| format!("`{}`", candidate.display()))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &statement.span,
+                            format!("could not find included file `{include_path}`"),
+                        )
+                        .with_note(format!("searched {searched}")),
+                    );
+                    continue;
+                };
+
+                if let Some(start) = include_chain
+                    .iter()
+                    .position(|file| file.canonical == path.canonical)
+                {
+                    let cycle = include_chain[start..]
+                        .iter()
+                        .chain([&path])
+                        .map(|file| file.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ");
+                    diagnostics.push(
+                        Diagnostic::error_at(
+                            &statement.span,
+                            format!("including `{include_path}` would create a cycle"),
+                        )
+                        .with_note(format!("include cycle: {cycle}")),
+                    );
+                    continue;
+                }
+
+                let namespace = match alias {
+                    Some(alias) if namespace.is_empty() => alias.clone(),
+                    Some(alias) => format!("{namespace}::{alias}"),
+                    None => namespace.to_owned(),
+                };
+                if !self
+                    .seen
+                    .insert((path.canonical.clone(), namespace.clone()))
+                {
+                    continue;
                 }
-            })
-            .collect();
 
-        for include in include_statements {
-            if let Statement::IncludeStatement(file_name) = include {
-                self.file_stack.insert(0, file_name);
+                self.file_stack.insert(
+                    0,
+                    PendingInclude {
+                        path,
+                        span: statement.span.clone(),
+                        include_chain: include_chain.to_vec(),
+                        namespace,
+                    },
+                );
             }
         }
     }
 
-    pub fn read_file_contents(file_name: String) -> String {
-        std::fs::read_to_string(file_name).unwrap()
+    pub fn read_file_contents(&self, file_name: &str) -> std::io::Result<String> {
+        self.loader.read(Path::new(file_name))
+    }
+
+    // The first existing file an include could refer to
+    fn find_include(&self, current_file: &Path, include_path: &str) -> Option<SourcePath> {
+        self.search_locations(current_file, include_path)
+            .find_map(|candidate| SourcePath::new(&candidate, self.loader).ok())
+    }
+
+    // Includes are looked up next to the including file, then in each include path in order
+    fn search_locations<'b>(
+        &'b self,
+        current_file: &Path,
+        include_path: &'b str,
+    ) -> impl Iterator<Item = PathBuf> + 'b {
+        let relative = FileManager::resolve_path(current_file, include_path);
+        let search_paths = self
+            .include_paths
+            .iter()
+            .filter(move |_| Path::new(include_path).is_relative())
+            .map(move |dir| dir.join(include_path));
+
+        std::iter::once(relative).chain(search_paths)
     }
 
     pub fn resolve_path(current_file: &Path, include_path: &str) -> PathBuf {
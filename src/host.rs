@@ -0,0 +1,257 @@
+// The world a program runs in
+//
+// Instructions that read the execution environment or world state, or that emit side effects, are
+// forwarded by the interpreter to a host. `InMemoryHost` seeds that state from a fixture and keeps
+// everything the program emits so that tests can make assertions about it.
+
+use std::collections::BTreeMap;
+
+use num_bigint::BigUint;
+use num_traits::Num;
+use serde::Deserialize;
+
+/// A value read by one of the execution environment instructions, e.g. `SENDER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentVariable {
+    Address,
+    StorageAddress,
+    Sender,
+    FeePerL2Gas,
+    FeePerDaGas,
+    TransactionFee,
+    ContractCallDepth,
+    ChainId,
+    Version,
+    BlockNumber,
+    Timestamp,
+    Coinbase,
+    BlockL2GasLimit,
+    BlockDaGasLimit,
+}
+
+/// The execution environment and world state that a program is run against.
+pub trait Host {
+    fn environment(&self, variable: EnvironmentVariable) -> BigUint;
+    fn calldata(&self) -> &[BigUint];
+
+    fn storage_read(&mut self, slot: &BigUint) -> BigUint;
+    fn storage_write(&mut self, slot: BigUint, value: BigUint);
+
+    fn note_hash_exists(&self, note_hash: &BigUint, leaf_index: &BigUint) -> bool;
+    fn emit_note_hash(&mut self, note_hash: BigUint);
+
+    fn nullifier_exists(&self, nullifier: &BigUint, address: &BigUint) -> bool;
+    fn emit_nullifier(&mut self, nullifier: BigUint) -> Result<(), String>;
+
+    fn l1_to_l2_message_exists(&self, message_hash: &BigUint, leaf_index: &BigUint) -> bool;
+
+    fn emit_unencrypted_log(&mut self, log: Vec<BigUint>);
+    fn send_l2_to_l1_message(&mut self, recipient: BigUint, content: BigUint);
+}
+
+/// A host whose state lives in memory, recording everything the program emits.
+///
+/// Note hashes and L1 to L2 messages are found by their index in the seeded lists, and seeded
+/// nullifiers belong to the contract's own address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InMemoryHost {
+    pub environment: BTreeMap<EnvironmentVariable, BigUint>,
+    pub calldata: Vec<BigUint>,
+    pub storage: BTreeMap<BigUint, BigUint>,
+    pub note_hashes: Vec<BigUint>,
+    pub nullifiers: Vec<BigUint>,
+    pub l1_to_l2_messages: Vec<BigUint>,
+
+    pub emitted_note_hashes: Vec<BigUint>,
+    pub emitted_nullifiers: Vec<BigUint>,
+    pub unencrypted_logs: Vec<Vec<BigUint>>,
+    pub l2_to_l1_messages: Vec<(BigUint, BigUint)>,
+}
+
+impl InMemoryHost {
+    /// Seed the host from a JSON fixture, values may be numbers, decimal strings or hex strings.
+    ///
+    /// ```json
+    /// {
+    ///     "environment": { "sender": "0x1234", "timestamp": 1700000000 },
+    ///     "calldata": [1, 2],
+    ///     "storage": { "1": "0x2a" },
+    ///     "note_hashes": [], "nullifiers": [], "l1_to_l2_messages": []
+    /// }
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let fixture: Fixture =
+            serde_json::from_str(json).map_err(|error| format!("invalid fixture: {error}"))?;
+
+        let values = |values: Vec<Field>| values.into_iter().map(|value| value.0).collect();
+        let storage = fixture
+            .storage
+            .into_iter()
+            .map(|(slot, value)| Ok((parse_field(&slot)?, value.0)))
+            .collect::<Result<_, String>>()
+            .map_err(|error| format!("invalid fixture: storage slot {error}"))?;
+
+        Ok(InMemoryHost {
+            environment: fixture
+                .environment
+                .into_iter()
+                .map(|(variable, value)| (variable, value.0))
+                .collect(),
+            calldata: values(fixture.calldata),
+            storage,
+            note_hashes: values(fixture.note_hashes),
+            nullifiers: values(fixture.nullifiers),
+            l1_to_l2_messages: values(fixture.l1_to_l2_messages),
+            ..Default::default()
+        })
+    }
+}
+
+impl Host for InMemoryHost {
+    fn environment(&self, variable: EnvironmentVariable) -> BigUint {
+        self.environment.get(&variable).cloned().unwrap_or_default()
+    }
+
+    fn calldata(&self) -> &[BigUint] {
+        &self.calldata
+    }
+
+    fn storage_read(&mut self, slot: &BigUint) -> BigUint {
+        self.storage.get(slot).cloned().unwrap_or_default()
+    }
+
+    fn storage_write(&mut self, slot: BigUint, value: BigUint) {
+        self.storage.insert(slot, value);
+    }
+
+    fn note_hash_exists(&self, note_hash: &BigUint, leaf_index: &BigUint) -> bool {
+        find_leaf(&self.note_hashes, leaf_index) == Some(note_hash)
+    }
+
+    fn emit_note_hash(&mut self, note_hash: BigUint) {
+        self.emitted_note_hashes.push(note_hash);
+    }
+
+    fn nullifier_exists(&self, nullifier: &BigUint, address: &BigUint) -> bool {
+        *address == self.environment(EnvironmentVariable::Address)
+            && (self.nullifiers.contains(nullifier) || self.emitted_nullifiers.contains(nullifier))
+    }
+
+    fn emit_nullifier(&mut self, nullifier: BigUint) -> Result<(), String> {
+        let address = self.environment(EnvironmentVariable::Address);
+        if self.nullifier_exists(&nullifier, &address) {
+            return Err(format!("nullifier `{nullifier}` already exists"));
+        }
+        self.emitted_nullifiers.push(nullifier);
+        Ok(())
+    }
+
+    fn l1_to_l2_message_exists(&self, message_hash: &BigUint, leaf_index: &BigUint) -> bool {
+        find_leaf(&self.l1_to_l2_messages, leaf_index) == Some(message_hash)
+    }
+
+    fn emit_unencrypted_log(&mut self, log: Vec<BigUint>) {
+        self.unencrypted_logs.push(log);
+    }
+
+    fn send_l2_to_l1_message(&mut self, recipient: BigUint, content: BigUint) {
+        self.l2_to_l1_messages.push((recipient, content));
+    }
+}
+
+fn find_leaf<'a>(leaves: &'a [BigUint], leaf_index: &BigUint) -> Option<&'a BigUint> {
+    let index: usize = leaf_index.try_into().ok()?;
+    leaves.get(index)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Fixture {
+    environment: BTreeMap<EnvironmentVariable, Field>,
+    calldata: Vec<Field>,
+    storage: BTreeMap<String, Field>,
+    note_hashes: Vec<Field>,
+    nullifiers: Vec<Field>,
+    l1_to_l2_messages: Vec<Field>,
+}
+
+// A fixture value, written as a number or as a decimal or hex string for values beyond 64 bits
+#[derive(Deserialize)]
+#[serde(try_from = "RawField")]
+struct Field(BigUint);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawField {
+    Number(u64),
+    String(String),
+}
+
+impl TryFrom<RawField> for Field {
+    type Error = String;
+
+    fn try_from(raw: RawField) -> Result<Self, Self::Error> {
+        match raw {
+            RawField::Number(value) => Ok(Field(BigUint::from(value))),
+            RawField::String(value) => parse_field(&value).map(Field),
+        }
+    }
+}
+
+fn parse_field(value: &str) -> Result<BigUint, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => BigUint::from_str_radix(hex, 16),
+        None => BigUint::from_str_radix(value, 10),
+    };
+    parsed.map_err(|_| format!("`{value}` is not a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::Zero;
+
+    #[test]
+    fn hosts_are_seeded_from_fixtures() {
+        let host = InMemoryHost::from_json(
+            r#"{
+                "environment": { "sender": "0x1234", "timestamp": 1700000000 },
+                "calldata": [1, "2", "0x03"],
+                "storage": { "0x01": 42 },
+                "note_hashes": ["0xff"]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            host.environment(EnvironmentVariable::Sender),
+            BigUint::from(0x1234u32)
+        );
+        assert_eq!(
+            host.environment(EnvironmentVariable::Timestamp),
+            BigUint::from(1700000000u32)
+        );
+        assert_eq!(
+            host.environment(EnvironmentVariable::ChainId),
+            BigUint::zero()
+        );
+        assert_eq!(host.calldata(), [1u32, 2, 3].map(BigUint::from).as_slice());
+        assert_eq!(host.storage[&BigUint::from(1u32)], BigUint::from(42u32));
+        assert!(host.note_hash_exists(&BigUint::from(0xffu32), &BigUint::zero()));
+        assert!(!host.note_hash_exists(&BigUint::from(0xffu32), &BigUint::from(1u32)));
+    }
+
+    #[test]
+    fn invalid_fixtures_are_rejected() {
+        let error = |json: &str| InMemoryHost::from_json(json).unwrap_err();
+
+        assert!(error(r#"{ "calldata": ["0xzz"] }"#).contains("`0xzz` is not a number"));
+        assert!(error(r#"{ "environment": { "gas": 1 } }"#).contains("unknown variant `gas`"));
+        assert!(error(r#"{ "state": {} }"#).contains("unknown field `state`"));
+        assert_eq!(
+            error(r#"{ "storage": { "one": 1 } }"#),
+            "invalid fixture: storage slot `one` is not a number"
+        );
+    }
+}
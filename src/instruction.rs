@@ -1,7 +1,11 @@
-use crate::{opcodes::Opcode, parser::Operand};
+use crate::{
+    isa::{Isa, OpcodeSpec, OperandKind, OperandWidth},
+    opcodes::Opcode,
+    parser::{Operand, TypeTag},
+};
 
-// An instruction is a pairing of an opcode and its operands.
-#[derive(Debug)]
+/// An instruction is a pairing of an opcode and its operands.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub indirect: bool,
@@ -17,45 +21,117 @@ impl Instruction {
         }
     }
 
-    // Append the instruction to a buffer, todo: probably best as a trait?
-    // TODO: more granularity on a per opcode basis? ones that do not have indirect are not supported
-    pub fn append_to_buffer(&self, buffer: &mut Vec<u8>) {
-        buffer.push(self.opcode as u8);
+    // Check the operands against the opcode's spec in the given instruction set
+    pub(crate) fn validate(&self, isa: Isa) -> Result<OpcodeSpec, EncodingError> {
+        let name = self.opcode.name();
+        let spec = isa.spec(self.opcode).ok_or_else(|| {
+            EncodingError::new(
+                None,
+                format!("{name} is not part of the {isa} instruction set"),
+            )
+        })?;
+
+        if self.indirect && !spec.indirect {
+            return Err(EncodingError::new(
+                None,
+                format!("{name} does not support indirect addressing"),
+            ));
+        }
+
+        if self.operands.len() != spec.operands.len() {
+            let expected = spec.operands.len();
+            return Err(EncodingError::new(
+                None,
+                format!(
+                    "{name} expects {expected} operand{}, but {} {} given",
+                    if expected == 1 { "" } else { "s" },
+                    self.operands.len(),
+                    if self.operands.len() == 1 {
+                        "was"
+                    } else {
+                        "were"
+                    },
+                ),
+            ));
+        }
+
+        for (index, (operand, operand_spec)) in self.operands.iter().zip(spec.operands).enumerate()
+        {
+            let is_tag = matches!(operand, Operand::Tag(_));
+            let valid = match operand_spec.kind {
+                // Tags may also be written as their numeric value
+                OperandKind::Tag => is_tag || matches!(operand, Operand::Decimal(_)),
+                _ => !is_tag,
+            };
+            if !valid {
+                return Err(EncodingError::new(
+                    Some(index),
+                    format!(
+                        "operand {} of {name} must be {}",
+                        index + 1,
+                        operand_spec.kind.description()
+                    ),
+                ));
+            }
+        }
+
+        Ok(spec)
+    }
+
+    // Append the instruction to a buffer, encoding each operand as described by the opcode's spec
+    pub(crate) fn append_to_buffer(
+        &self,
+        isa: Isa,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), EncodingError> {
+        let spec = self.validate(isa)?;
+
+        buffer.push(spec.value);
         buffer.push(self.indirect as u8);
 
-        match self.opcode {
-            // Opcodes that contain tags will push their first opcode (the tag)
-            // as a u8
-            Opcode::SET | Opcode::CAST => {
-                // yuck
-                let mut ops = self.operands.clone();
-
-                let first_operand = ops.remove(0);
-                let tag = match first_operand {
-                    Operand::Decimal(tag) => tag as u8,
-                    Operand::Tag(tag) => tag as u8,
-                    _ => panic!("YOU DONKEY: First operand of CAST or SET must be a tag"),
+        // The type given by the tag operand, which sizes any tagged operands after it
+        let mut tag = None;
+        for (index, (operand, operand_spec)) in self.operands.iter().zip(spec.operands).enumerate()
+        {
+            let at_operand = |message| EncodingError::new(Some(index), message);
+
+            if operand_spec.kind == OperandKind::Tag {
+                let value = match operand {
+                    Operand::Tag(tag) => tag.clone(),
+                    Operand::Decimal(value) => u8::try_from(*value)
+                        .map_err(|_| format!("invalid type tag `{value}`"))
+                        .and_then(TypeTag::try_from)
+                        .map_err(at_operand)?,
+                    _ => unreachable!("tags are validated"),
                 };
+                buffer.push(value.clone() as u8);
+                tag = Some(value);
+                continue;
+            }
 
-                // First operand MUST be a numeric, i.e. the tag
-                buffer.push(tag);
+            let bits = match operand_spec.width {
+                OperandWidth::Bytes(bytes) => bytes * 8,
+                OperandWidth::Tagged => tag.as_ref().expect("tagged operands follow a tag").bits(),
+            };
+            buffer.extend(operand.to_be_bytes(bits).map_err(at_operand)?);
+        }
 
-                // If there is a second operand, we provide the hint
-                // !this case is really only relevant for SET
-                // !to be bytes with hint will ignore it if no literal is provided
-                let second_operand = ops.remove(0);
-                buffer.extend(second_operand.to_be_bytes_with_hint(tag.into()));
+        Ok(())
+    }
+}
 
-                // TODO: support set for large types
-                for operand in &ops {
-                    buffer.extend(operand.to_be_bytes());
-                }
-            }
-            _ => {
-                for operand in &self.operands {
-                    buffer.extend(operand.to_be_bytes());
-                }
-            }
+/// Why an instruction could not be encoded, along with the operand at fault if there is one
+#[derive(Debug)]
+pub struct EncodingError {
+    pub operand: Option<usize>,
+    pub message: String,
+}
+
+impl EncodingError {
+    fn new(operand: Option<usize>, message: impl Into<String>) -> Self {
+        EncodingError {
+            operand,
+            message: message.into(),
         }
     }
 }
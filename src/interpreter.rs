@@ -0,0 +1,807 @@
+// A reference interpreter for assembled programs
+//
+// Programs run against tagged memory: every cell holds a value along with the type tag it was
+// written with, and arithmetic wraps at the width of that tag. `ff` values are elements of the
+// BN254 scalar field. Cells that have never been written read as `ff 0`.
+//
+// Every instruction costs a unit of L2 gas, and each field of data a program emits costs a unit of DA
+// gas, until the AVM settles on a gas schedule. Running out of either is a fault.
+//
+// The execution environment and world state come from a `Host`. Values read from the host are
+// written to memory as `ff`, and the results of existence checks as `u8`.
+
+use std::{collections::HashMap, fmt};
+
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::{
+    disassembler::{decode, decode_hex, DisassemblyError},
+    host::{EnvironmentVariable, Host},
+    instruction::Instruction,
+    isa::{Isa, OperandKind},
+    opcodes::Opcode,
+    parser::{Operand, TypeTag},
+};
+
+// The order of the BN254 scalar field
+const FIELD_MODULUS: &str = "30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001";
+
+fn field_modulus() -> BigUint {
+    BigUint::parse_bytes(FIELD_MODULUS.as_bytes(), 16).expect("the field modulus is valid hex")
+}
+
+/// How a program finished, along with the data it returned or reverted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub reverted: bool,
+    pub output: Vec<BigUint>,
+}
+
+/// The gas available to a program in each of the dimensions it is charged in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gas {
+    pub l2: u64,
+    pub da: u64,
+}
+
+impl Default for Gas {
+    fn default() -> Self {
+        Gas {
+            l2: 1_000_000,
+            da: 1_000_000,
+        }
+    }
+}
+
+// The most cells that can be returned, reverted with or logged at once
+const MAX_RANGE: u64 = 1 << 20;
+
+// Take `amount` from the remaining gas of one dimension
+fn spend(remaining: &mut u64, amount: u64, dimension: &str) -> Result<(), String> {
+    *remaining = remaining
+        .checked_sub(amount)
+        .ok_or_else(|| format!("out of {dimension} gas"))?;
+    Ok(())
+}
+
+/// A program that could not be run to completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    Decode(DisassemblyError),
+    Fault { pc: usize, message: String },
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::Decode(error) => write!(f, "{error}"),
+            ExecutionError::Fault { pc, message } => {
+                write!(f, "{message} (at instruction {pc})")
+            }
+        }
+    }
+}
+
+/// Run hex encoded bytecode, as produced by the compiler, against `host` until it returns or
+/// reverts.
+pub fn execute(bytecode: &str, isa: Isa, host: &mut dyn Host) -> Result<Outcome, ExecutionError> {
+    let bytes = decode_hex(bytecode).map_err(ExecutionError::Decode)?;
+    let instructions = decode(&bytes, isa).map_err(ExecutionError::Decode)?;
+    Machine::new(instructions, isa, host).run()
+}
+
+// A value held in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Value {
+    pub tag: TypeTag,
+    pub value: BigUint,
+}
+
+impl Value {
+    fn new(tag: TypeTag, value: BigUint) -> Self {
+        let value = wrap(&tag, value);
+        Value { tag, value }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.tag, self.value)
+    }
+}
+
+// Reduce a value to the range of its tag
+fn wrap(tag: &TypeTag, value: BigUint) -> BigUint {
+    match tag {
+        TypeTag::FF => value % field_modulus(),
+        _ => value % (BigUint::one() << tag.bits()),
+    }
+}
+
+// One past the largest value a tag can hold
+fn modulus(tag: &TypeTag) -> BigUint {
+    match tag {
+        TypeTag::FF => field_modulus(),
+        _ => BigUint::one() << tag.bits(),
+    }
+}
+
+pub(crate) struct Machine<'a> {
+    instructions: Vec<Instruction>,
+    isa: Isa,
+    host: &'a mut dyn Host,
+    pub pc: usize,
+    pub memory: HashMap<u64, Value>,
+    // Where to continue from after each `INTERNALRETURN`
+    pub call_stack: Vec<usize>,
+    // What is left to spend
+    pub gas: Gas,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(instructions: Vec<Instruction>, isa: Isa, host: &'a mut dyn Host) -> Self {
+        Machine {
+            instructions,
+            isa,
+            host,
+            pc: 0,
+            memory: HashMap::new(),
+            call_stack: Vec::new(),
+            gas: Gas::default(),
+        }
+    }
+
+    // Run until the program returns or reverts. Running off the end of the program returns
+    // nothing
+    pub fn run(&mut self) -> Result<Outcome, ExecutionError> {
+        loop {
+            if let Some(outcome) = self.step()? {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    // Execute a single instruction, returning the outcome if it ended the program
+    pub fn step(&mut self) -> Result<Option<Outcome>, ExecutionError> {
+        let pc = self.pc;
+        if pc >= self.instructions.len() {
+            return Ok(Some(Outcome {
+                reverted: false,
+                output: Vec::new(),
+            }));
+        }
+        self.pc += 1;
+
+        self.execute_instruction(pc).map_err(|message| {
+            // Leave the program counter on the instruction that failed
+            self.pc = pc;
+            ExecutionError::Fault { pc, message }
+        })
+    }
+
+    pub fn read(&self, offset: u64) -> Value {
+        self.memory.get(&offset).cloned().unwrap_or(Value {
+            tag: TypeTag::FF,
+            value: BigUint::zero(),
+        })
+    }
+
+    fn execute_instruction(&mut self, pc: usize) -> Result<Option<Outcome>, String> {
+        let instruction = &self.instructions[pc];
+        let opcode = instruction.opcode;
+        let name = opcode.name();
+        let spec = instruction
+            .validate(self.isa)
+            .map_err(|error| error.message)?;
+        spend(&mut self.gas.l2, 1, "L2")?;
+
+        // Resolve each operand to a number, following memory offsets through memory when the
+        // instruction is indirect
+        let mut operands = Vec::new();
+        for (operand, operand_spec) in instruction.operands.iter().zip(spec.operands) {
+            let value = match operand {
+                Operand::Tag(tag) => BigUint::from(tag.clone() as u8),
+                operand => operand.evaluate()?,
+            };
+            let value = match operand_spec.kind {
+                OperandKind::Memory if instruction.indirect => {
+                    let pointer = self.read(to_u64(&value)?);
+                    pointer.value
+                }
+                _ => value,
+            };
+            operands.push(value);
+        }
+        let offset = |index: usize| to_u64(&operands[index]);
+
+        match opcode {
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::FDIV
+            | Opcode::EQ
+            | Opcode::LT
+            | Opcode::LTE
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SHR => {
+                let (a, b) = (self.read(offset(0)?), self.read(offset(1)?));
+                if a.tag != b.tag {
+                    return Err(format!(
+                        "{name} operands have different tags: {} and {}",
+                        a.tag, b.tag
+                    ));
+                }
+                let result = binary_operation(opcode, a, b)?;
+                self.memory.insert(offset(2)?, result);
+            }
+            Opcode::NOT => {
+                let a = self.read(offset(0)?);
+                require_integer(name, &a.tag)?;
+                let result = modulus(&a.tag) - BigUint::one() - &a.value;
+                self.memory.insert(offset(1)?, Value::new(a.tag, result));
+            }
+            Opcode::CAST => {
+                let tag = to_tag(&operands[0])?;
+                let a = self.read(offset(1)?);
+                self.memory.insert(offset(2)?, Value::new(tag, a.value));
+            }
+            Opcode::SET => {
+                let tag = to_tag(&operands[0])?;
+                if operands[1] >= modulus(&tag) {
+                    return Err(format!("value `{}` does not fit in {tag}", operands[1]));
+                }
+                self.memory
+                    .insert(offset(2)?, Value::new(tag, operands[1].clone()));
+            }
+            Opcode::MOV => {
+                let a = self.read(offset(0)?);
+                self.memory.insert(offset(1)?, a);
+            }
+            Opcode::CMOV => {
+                let condition = self.read(offset(2)?);
+                let source = if condition.is_zero() { 1 } else { 0 };
+                let value = self.read(offset(source)?);
+                self.memory.insert(offset(3)?, value);
+            }
+            Opcode::JUMP => self.pc = self.jump_target(&operands[0])?,
+            Opcode::JUMPI => {
+                if !self.read(offset(1)?).is_zero() {
+                    self.pc = self.jump_target(&operands[0])?;
+                }
+            }
+            Opcode::INTERNALCALL => {
+                let target = self.jump_target(&operands[0])?;
+                self.call_stack.push(self.pc);
+                self.pc = target;
+            }
+            Opcode::INTERNALRETURN => {
+                self.pc = self
+                    .call_stack
+                    .pop()
+                    .ok_or("INTERNALRETURN with an empty call stack")?;
+            }
+            Opcode::ADDRESS
+            | Opcode::STORAGEADDRESS
+            | Opcode::SENDER
+            | Opcode::FEEPERL2GAS
+            | Opcode::FEEPERDAGAS
+            | Opcode::TRANSACTIONFEE
+            | Opcode::CONTRACTCALLDEPTH
+            | Opcode::CHAINID
+            | Opcode::VERSION
+            | Opcode::BLOCKNUMBER
+            | Opcode::TIMESTAMP
+            | Opcode::COINBASE
+            | Opcode::BLOCKL2GASLIMIT
+            | Opcode::BLOCKDAGASLIMIT => {
+                let value = self.host.environment(environment_variable(opcode));
+                self.memory
+                    .insert(offset(0)?, Value::new(TypeTag::FF, value));
+            }
+            Opcode::CALLDATACOPY => {
+                // Some profiles read the offset and number of fields from memory
+                let count = |index: usize| match spec.operands[index].kind {
+                    OperandKind::Memory => to_u64(&self.read(offset(index)?).value),
+                    _ => to_u64(&operands[index]),
+                };
+                let (start, size) = (count(0)?, count(1)?);
+                let destination = offset(2)?;
+                for i in 0..size {
+                    let value = start
+                        .checked_add(i)
+                        .and_then(|index| self.host.calldata().get(index as usize))
+                        .ok_or_else(|| format!("calldata has fewer than {} fields", start + size))?
+                        .clone();
+                    let address = destination
+                        .checked_add(i)
+                        .ok_or("calldata overflows memory")?;
+                    self.memory.insert(address, Value::new(TypeTag::FF, value));
+                }
+            }
+            Opcode::SLOAD => {
+                let slot = self.read(offset(0)?).value;
+                let value = self.host.storage_read(&slot);
+                self.memory
+                    .insert(offset(1)?, Value::new(TypeTag::FF, value));
+            }
+            Opcode::SSTORE => {
+                let value = self.read(offset(0)?).value;
+                let slot = self.read(offset(1)?).value;
+                spend(&mut self.gas.da, 1, "DA")?;
+                self.host.storage_write(slot, value);
+            }
+            Opcode::NOTEHASHEXISTS | Opcode::NULLIFIEREXISTS | Opcode::L1TOL2MSGEXISTS => {
+                let (a, b) = (self.read(offset(0)?).value, self.read(offset(1)?).value);
+                let exists = match opcode {
+                    Opcode::NOTEHASHEXISTS => self.host.note_hash_exists(&a, &b),
+                    Opcode::NULLIFIEREXISTS => self.host.nullifier_exists(&a, &b),
+                    _ => self.host.l1_to_l2_message_exists(&a, &b),
+                };
+                self.memory.insert(
+                    offset(2)?,
+                    Value::new(TypeTag::U8, BigUint::from(exists as u8)),
+                );
+            }
+            Opcode::EMITNOTEHASH => {
+                let note_hash = self.read(offset(0)?).value;
+                spend(&mut self.gas.da, 1, "DA")?;
+                self.host.emit_note_hash(note_hash);
+            }
+            Opcode::EMITNULLIFIER => {
+                let nullifier = self.read(offset(0)?).value;
+                spend(&mut self.gas.da, 1, "DA")?;
+                self.host.emit_nullifier(nullifier)?;
+            }
+            Opcode::EMITUNENCRYPTEDLOG => {
+                let start = offset(0)?;
+                let size = to_u64(&self.read(offset(1)?).value)?;
+                // Charge for the log before reading it, so gas bounds how much is read
+                spend(&mut self.gas.da, size, "DA")?;
+                let log = self.read_range(start, size)?;
+                self.host.emit_unencrypted_log(log);
+            }
+            Opcode::SENDL2TOL1MSG => {
+                let recipient = self.read(offset(0)?).value;
+                let content = self.read(offset(1)?).value;
+                spend(&mut self.gas.da, 2, "DA")?;
+                self.host.send_l2_to_l1_message(recipient, content);
+            }
+            Opcode::L2GASLEFT | Opcode::DAGASLEFT => {
+                let left = match opcode {
+                    Opcode::L2GASLEFT => self.gas.l2,
+                    _ => self.gas.da,
+                };
+                self.memory
+                    .insert(offset(0)?, Value::new(TypeTag::FF, BigUint::from(left)));
+            }
+            Opcode::RETURN | Opcode::REVERT => {
+                let output = self.read_range(offset(0)?, to_u64(&operands[1])?)?;
+                return Ok(Some(Outcome {
+                    reverted: opcode == Opcode::REVERT,
+                    output,
+                }));
+            }
+            _ => return Err(format!("{name} is not supported by the interpreter")),
+        }
+
+        Ok(None)
+    }
+
+    // The values of `size` consecutive cells
+    fn read_range(&self, start: u64, size: u64) -> Result<Vec<BigUint>, String> {
+        // The size can come from memory, so check it before allocating room for the values
+        if size > MAX_RANGE {
+            return Err(format!(
+                "cannot read {size} cells at once, the limit is {MAX_RANGE}"
+            ));
+        }
+        (0..size)
+            .map(|i| {
+                let offset = start.checked_add(i).ok_or("data overflows memory")?;
+                Ok(self.read(offset).value)
+            })
+            .collect()
+    }
+
+    fn jump_target(&self, target: &BigUint) -> Result<usize, String> {
+        target
+            .to_usize()
+            .filter(|&target| target <= self.instructions.len())
+            .ok_or_else(|| format!("jump target `{target}` is outside of the program"))
+    }
+}
+
+fn environment_variable(opcode: Opcode) -> EnvironmentVariable {
+    match opcode {
+        Opcode::ADDRESS => EnvironmentVariable::Address,
+        Opcode::STORAGEADDRESS => EnvironmentVariable::StorageAddress,
+        Opcode::SENDER => EnvironmentVariable::Sender,
+        Opcode::FEEPERL2GAS => EnvironmentVariable::FeePerL2Gas,
+        Opcode::FEEPERDAGAS => EnvironmentVariable::FeePerDaGas,
+        Opcode::TRANSACTIONFEE => EnvironmentVariable::TransactionFee,
+        Opcode::CONTRACTCALLDEPTH => EnvironmentVariable::ContractCallDepth,
+        Opcode::CHAINID => EnvironmentVariable::ChainId,
+        Opcode::VERSION => EnvironmentVariable::Version,
+        Opcode::BLOCKNUMBER => EnvironmentVariable::BlockNumber,
+        Opcode::TIMESTAMP => EnvironmentVariable::Timestamp,
+        Opcode::COINBASE => EnvironmentVariable::Coinbase,
+        Opcode::BLOCKL2GASLIMIT => EnvironmentVariable::BlockL2GasLimit,
+        Opcode::BLOCKDAGASLIMIT => EnvironmentVariable::BlockDaGasLimit,
+        _ => unreachable!("{} does not read the environment", opcode.name()),
+    }
+}
+
+fn binary_operation(opcode: Opcode, a: Value, b: Value) -> Result<Value, String> {
+    let name = opcode.name();
+    let tag = a.tag;
+    let modulus = modulus(&tag);
+    let boolean = |value: bool| Value::new(TypeTag::U8, BigUint::from(value as u8));
+
+    let value = match opcode {
+        Opcode::ADD => a.value + b.value,
+        Opcode::SUB => a.value + &modulus - b.value,
+        Opcode::MUL => a.value * b.value,
+        Opcode::EQ => return Ok(boolean(a.value == b.value)),
+        Opcode::LT => return Ok(boolean(a.value < b.value)),
+        Opcode::LTE => return Ok(boolean(a.value <= b.value)),
+        Opcode::FDIV => {
+            if tag != TypeTag::FF {
+                return Err(format!("FDIV is only defined for ff values, not {tag}"));
+            }
+            if b.is_zero() {
+                return Err("FDIV divides by zero".to_owned());
+            }
+            // Fermat's little theorem gives the inverse in a prime field
+            let inverse = b.value.modpow(&(&modulus - 2u32), &modulus);
+            a.value * inverse
+        }
+        _ => {
+            require_integer(name, &tag)?;
+            match opcode {
+                Opcode::DIV if b.is_zero() => {
+                    return Err(format!("{name} divides by zero"));
+                }
+                Opcode::DIV => a.value / b.value,
+                Opcode::AND => a.value & b.value,
+                Opcode::OR => a.value | b.value,
+                Opcode::XOR => a.value ^ b.value,
+                // Shifting by the width of the tag or more clears every bit
+                Opcode::SHL => match b.value.to_usize() {
+                    Some(shift) if shift < tag.bits() => a.value << shift,
+                    _ => BigUint::zero(),
+                },
+                Opcode::SHR => match b.value.to_usize() {
+                    Some(shift) if shift < tag.bits() => a.value >> shift,
+                    _ => BigUint::zero(),
+                },
+                _ => unreachable!("{name} is not a binary operation"),
+            }
+        }
+    };
+
+    Ok(Value::new(tag, value))
+}
+
+fn require_integer(name: &str, tag: &TypeTag) -> Result<(), String> {
+    if *tag == TypeTag::FF {
+        return Err(format!("{name} is not defined for ff values"));
+    }
+    Ok(())
+}
+
+fn to_u64(value: &BigUint) -> Result<u64, String> {
+    value
+        .to_u64()
+        .ok_or_else(|| format!("memory offset `{value}` is out of range"))
+}
+
+fn to_tag(value: &BigUint) -> Result<TypeTag, String> {
+    value
+        .to_u8()
+        .ok_or_else(|| format!("invalid type tag `{value}`"))
+        .and_then(TypeTag::try_from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compiler::{compile_asm_with_options, CompileOptions},
+        host::InMemoryHost,
+    };
+
+    fn run_with(source: &str, host: &mut InMemoryHost) -> Result<Outcome, ExecutionError> {
+        let bytecode =
+            compile_asm_with_options(source.to_owned(), &CompileOptions::default()).unwrap();
+        execute(&bytecode, Isa::V1, host)
+    }
+
+    fn run(source: &str) -> Result<Outcome, ExecutionError> {
+        run_with(source, &mut InMemoryHost::default())
+    }
+
+    fn returned(source: &str) -> Vec<BigUint> {
+        let outcome = run(source).unwrap();
+        assert!(!outcome.reverted);
+        outcome.output
+    }
+
+    fn fault(source: &str) -> String {
+        run(source).unwrap_err().to_string()
+    }
+
+    fn values(values: &[u64]) -> Vec<BigUint> {
+        values.iter().map(|&value| BigUint::from(value)).collect()
+    }
+
+    #[test]
+    fn integer_arithmetic_wraps_at_the_tag_width() {
+        let output = returned(
+            "
+            set u8 200 0;
+            set u8 100 1;
+            add 0 1 2;
+            sub 1 0 3;
+            mul 0 1 4;
+            div 0 1 5;
+            not 0 6;
+            shl 0 1 7;
+            lt 1 0 8;
+            return 2 7;
+            ",
+        );
+        assert_eq!(output, values(&[44, 156, 32, 2, 55, 0, 1]));
+    }
+
+    #[test]
+    fn field_arithmetic_is_modulo_the_scalar_field() {
+        let output = returned(
+            "
+            set ff 0 0;
+            set ff 1 1;
+            set ff 2 2;
+            sub 0 1 3;
+            fdiv 1 2 4;
+            mul 4 2 5;
+            add 3 1 6;
+            return 3 4;
+            ",
+        );
+        let half = (field_modulus() + 1u32) / 2u32;
+        assert_eq!(
+            output,
+            vec![
+                field_modulus() - 1u32,
+                half,
+                BigUint::one(),
+                BigUint::zero()
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        // Sum the numbers from 5 down to 1
+        let output = returned(
+            "
+            set u32 0 0;
+            set u32 5 1;
+            set u32 1 2;
+            loop:
+                add 0 1 0;
+                sub 1 2 1;
+                jumpi @loop 1;
+            return 0 1;
+            ",
+        );
+        assert_eq!(output, values(&[15]));
+
+        let output = returned(
+            "
+            set u8 3 0;
+            internalcall @double;
+            internalcall @double;
+            return 0 1;
+            double:
+                add 0 0 0;
+                internalreturn;
+            ",
+        );
+        assert_eq!(output, values(&[12]));
+
+        // Running off the end returns nothing
+        assert_eq!(returned("set u8 1 0;"), values(&[]));
+    }
+
+    #[test]
+    fn memory_instructions() {
+        // Indirect operands hold the offsets to use
+        let output = returned(
+            "
+            set u64 10 0;
+            set u64 20 1;
+            set u8 7 10;
+            mov! 0 1;
+            return 20 1;
+            ",
+        );
+        assert_eq!(output, values(&[7]));
+
+        let output = returned(
+            "
+            set u8 1 0;
+            set u8 2 1;
+            set u8 0 2;
+            cmov 0 1 2 3;
+            set u16 300 4;
+            cast u8 4 5;
+            return 3 3;
+            ",
+        );
+        assert_eq!(output, values(&[2, 300, 44]));
+    }
+
+    #[test]
+    fn world_state_comes_from_the_host() {
+        let mut host = InMemoryHost::from_json(
+            r#"{
+                "environment": { "address": 7, "sender": "0x1234" },
+                "calldata": [10, 20, 30],
+                "storage": { "1": 100 },
+                "note_hashes": [5],
+                "nullifiers": [6]
+            }"#,
+        )
+        .unwrap();
+
+        let output = run_with(
+            "
+            sender 0;
+            calldatacopy 1 2 1;
+            set ff 1 3;
+            sload 3 4;
+            add 4 1 5;
+            sstore 5 3;
+            set ff 5 6;
+            set ff 0 7;
+            notehashexists 6 7 8;
+            set ff 6 9;
+            address 10;
+            nullifierexists 9 10 11;
+            set ff 99 12;
+            emitnullifier 12;
+            emitnotehash 6;
+            set u8 2 13;
+            emitunencryptedlog 1 13;
+            sendl2tol1msg 0 2;
+            return 0 3;
+            ",
+            &mut host,
+        )
+        .unwrap();
+
+        assert_eq!(output.output, values(&[0x1234, 20, 30]));
+        assert_eq!(host.storage[&BigUint::from(1u32)], BigUint::from(120u32));
+        assert_eq!(host.emitted_nullifiers, values(&[99]));
+        assert_eq!(host.emitted_note_hashes, values(&[5]));
+        assert_eq!(host.unencrypted_logs, vec![values(&[20, 30])]);
+        assert_eq!(
+            host.l2_to_l1_messages,
+            vec![(BigUint::from(0x1234u32), BigUint::from(30u32))]
+        );
+
+        // Nullifiers only exist for the address they were emitted by
+        let output = run_with(
+            "
+            set ff 5 0;
+            set ff 0 1;
+            set ff 6 2;
+            address 3;
+            set ff 8 4;
+            notehashexists 0 1 10;
+            nullifierexists 2 3 11;
+            nullifierexists 2 4 12;
+            return 10 3;
+            ",
+            &mut host,
+        )
+        .unwrap();
+        assert_eq!(output.output, values(&[1, 1, 0]));
+
+        assert_eq!(
+            run_with("set ff 6 0; emitnullifier 0;", &mut host)
+                .unwrap_err()
+                .to_string(),
+            "nullifier `6` already exists (at instruction 1)"
+        );
+        assert_eq!(
+            run_with("calldatacopy 2 5 0;", &mut host)
+                .unwrap_err()
+                .to_string(),
+            "calldata has fewer than 7 fields (at instruction 0)"
+        );
+    }
+
+    #[test]
+    fn gas_is_metered() {
+        let output = returned(
+            "
+            l2gasleft 0;
+            set u8 2 1;
+            emitunencryptedlog 1 1;
+            dagasleft 2;
+            return 0 3;
+            ",
+        );
+        let gas = Gas::default();
+        assert_eq!(output, values(&[gas.l2 - 1, 2, gas.da - 2]));
+
+        // Programs that never finish run out of gas
+        assert_eq!(
+            fault("loop: jump @loop;"),
+            "out of L2 gas (at instruction 0)"
+        );
+        // Logs are paid for before they are read
+        assert_eq!(
+            fault("set u32 2000000 0; emitunencryptedlog 1 0;"),
+            "out of DA gas (at instruction 1)"
+        );
+    }
+
+    #[test]
+    fn revert_produces_output_data() {
+        let outcome = run("set u8 42 0; revert 0 1;").unwrap();
+        assert_eq!(
+            outcome,
+            Outcome {
+                reverted: true,
+                output: values(&[42])
+            }
+        );
+    }
+
+    #[test]
+    fn faults() {
+        assert_eq!(
+            fault("set u8 1 0; set u16 1 1; add 0 1 2;"),
+            "ADD operands have different tags: u8 and u16 (at instruction 2)"
+        );
+        assert_eq!(
+            fault("set u8 1 0; set u8 0 1; div 0 1 2;"),
+            "DIV divides by zero (at instruction 2)"
+        );
+        assert_eq!(
+            fault("set ff 1 0; div 0 0 1;"),
+            "DIV is not defined for ff values (at instruction 1)"
+        );
+        assert_eq!(
+            fault("set u8 1 0; fdiv 0 0 1;"),
+            "FDIV is only defined for ff values, not u8 (at instruction 1)"
+        );
+        assert_eq!(
+            fault("internalreturn;"),
+            "INTERNALRETURN with an empty call stack (at instruction 0)"
+        );
+        assert_eq!(
+            fault("jump 5;"),
+            "jump target `5` is outside of the program (at instruction 0)"
+        );
+        assert_eq!(
+            fault("return 0 1000000000000;"),
+            "cannot read 1000000000000 cells at once, the limit is 1048576 (at instruction 0)"
+        );
+        assert_eq!(
+            fault("set u8 0 0; keccak 0 0 0;"),
+            "KECCAK is not supported by the interpreter (at instruction 1)"
+        );
+    }
+}
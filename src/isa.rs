@@ -0,0 +1,510 @@
+// Instruction set profiles
+//
+// The AVM spec is still changing, so each revision we assemble for owns the byte value of every
+// opcode and the layout of its operands. Mnemonics are shared between profiles, an opcode that a
+// profile does not define is an error when assembling against it. Only v1, the revision `Opcode`
+// tracks, ships today; the tests use a made up second profile to check that profiles stay apart.
+
+use std::{fmt, str::FromStr};
+
+use crate::opcodes::Opcode;
+
+/// A revision of the AVM instruction set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    /// The original numbering, with 8 byte operands
+    #[default]
+    V1,
+    // Renumbered opcodes with 4 byte operands, dropping SHA256 and PEDERSEN. It does not
+    // follow any spec revision, so it can't be selected
+    #[cfg(test)]
+    Fixture,
+}
+
+impl Isa {
+    pub const ALL: [Isa; 1] = [Isa::V1];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Isa::V1 => "v1",
+            #[cfg(test)]
+            Isa::Fixture => "fixture",
+        }
+    }
+
+    /// The opcode encoded as the given byte in this profile.
+    pub fn opcode(&self, value: u8) -> Option<Opcode> {
+        Opcode::ALL
+            .into_iter()
+            .find(|&opcode| self.spec(opcode).is_some_and(|spec| spec.value == value))
+    }
+
+    /// How the opcode is encoded in this profile, if it is part of it.
+    pub(crate) fn spec(&self, opcode: Opcode) -> Option<OpcodeSpec> {
+        match self {
+            Isa::V1 => v1_spec(opcode),
+            #[cfg(test)]
+            Isa::Fixture => fixture_spec(opcode),
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Isa {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Isa::ALL
+            .into_iter()
+            .find(|isa| isa.name() == name)
+            .ok_or_else(|| {
+                let available: Vec<&str> = Isa::ALL.iter().map(Isa::name).collect();
+                format!(
+                    "unknown instruction set `{name}`, expected one of {}",
+                    available.join(", ")
+                )
+            })
+    }
+}
+
+/// The byte value of an opcode and the shape of its operands, used both to validate and to
+/// encode instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeSpec {
+    pub value: u8,
+    pub operands: &'static [OperandSpec],
+    /// Whether the opcode may be written with `!` to address its operands indirectly
+    pub indirect: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperandSpec {
+    pub kind: OperandKind,
+    pub width: OperandWidth,
+}
+
+impl OperandSpec {
+    const fn new(kind: OperandKind, width: OperandWidth) -> Self {
+        OperandSpec { kind, width }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Memory,
+    Tag,
+    Immediate,
+    JumpTarget,
+}
+
+impl OperandKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            OperandKind::Memory => "a memory offset",
+            OperandKind::Tag => "a type tag",
+            OperandKind::Immediate => "an immediate value",
+            OperandKind::JumpTarget => "a jump target",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandWidth {
+    Bytes(usize),
+    // The width of the type given by the opcode's tag operand
+    Tagged,
+}
+
+// Shorthands for the operand tables below
+const T: OperandSpec = OperandSpec::new(OperandKind::Tag, OperandWidth::Bytes(1));
+// An immediate as wide as the type given by the preceding tag
+const V: OperandSpec = OperandSpec::new(OperandKind::Immediate, OperandWidth::Tagged);
+
+fn v1_spec(opcode: Opcode) -> Option<OpcodeSpec> {
+    const M: OperandSpec = OperandSpec::new(OperandKind::Memory, OperandWidth::Bytes(8));
+    const I: OperandSpec = OperandSpec::new(OperandKind::Immediate, OperandWidth::Bytes(8));
+    const J: OperandSpec = OperandSpec::new(OperandKind::JumpTarget, OperandWidth::Bytes(8));
+
+    let (operands, indirect): (&'static [OperandSpec], bool) = match opcode {
+        // Compute
+        Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::DIV
+        | Opcode::FDIV
+        | Opcode::EQ
+        | Opcode::LT
+        | Opcode::LTE
+        | Opcode::AND
+        | Opcode::OR
+        | Opcode::XOR
+        | Opcode::SHL
+        | Opcode::SHR => (&[M, M, M], true),
+        Opcode::NOT => (&[M, M], true),
+        Opcode::CAST => (&[T, M, M], true),
+        // Execution environment
+        Opcode::ADDRESS
+        | Opcode::STORAGEADDRESS
+        | Opcode::SENDER
+        | Opcode::FEEPERL2GAS
+        | Opcode::FEEPERDAGAS
+        | Opcode::TRANSACTIONFEE
+        | Opcode::CONTRACTCALLDEPTH
+        | Opcode::CHAINID
+        | Opcode::VERSION
+        | Opcode::BLOCKNUMBER
+        | Opcode::TIMESTAMP
+        | Opcode::COINBASE
+        | Opcode::BLOCKL2GASLIMIT
+        | Opcode::BLOCKDAGASLIMIT => (&[M], true),
+        // Offset into the calldata, number of fields, destination
+        Opcode::CALLDATACOPY => (&[I, I, M], true),
+        // Gas
+        Opcode::L2GASLEFT | Opcode::DAGASLEFT => (&[M], true),
+        // Control flow
+        Opcode::JUMP | Opcode::INTERNALCALL => (&[J], false),
+        Opcode::JUMPI => (&[J, M], true),
+        Opcode::INTERNALRETURN => (&[], false),
+        // Memory
+        Opcode::SET => (&[T, V, M], true),
+        Opcode::MOV => (&[M, M], true),
+        Opcode::CMOV => (&[M, M, M, M], true),
+        // World state
+        Opcode::SLOAD | Opcode::SSTORE => (&[M, M], true),
+        Opcode::NOTEHASHEXISTS | Opcode::NULLIFIEREXISTS | Opcode::L1TOL2MSGEXISTS => {
+            (&[M, M, M], true)
+        }
+        Opcode::EMITNOTEHASH | Opcode::EMITNULLIFIER => (&[M], true),
+        Opcode::HEADERMEMBER => (&[M, M, M], true),
+        Opcode::GETCONTRACTINSTANCE => (&[M, M], true),
+        Opcode::EMITUNENCRYPTEDLOG | Opcode::SENDL2TOL1MSG => (&[M, M], true),
+        // External calls
+        // Gas, address, args, args size, return data, return size, success, selector
+        Opcode::CALL | Opcode::STATICCALL | Opcode::DELEGATECALL => {
+            (&[M, M, M, M, M, I, M, M], true)
+        }
+        Opcode::RETURN | Opcode::REVERT => (&[M, I], true),
+        // Misc
+        // Message, message size, fields, fields size
+        Opcode::DEBUGLOG => (&[M, I, M, M], true),
+        // Gadgets
+        Opcode::KECCAK | Opcode::SHA256 => (&[M, M, M], true),
+        Opcode::POSEIDON2 => (&[M, M], true),
+        Opcode::PEDERSEN => (&[M, M, M, M], true),
+        // Both points as x, y and is infinite, then the destination
+        Opcode::ECADD => (&[M, M, M, M, M, M, M], true),
+        Opcode::MSM => (&[M, M, M, M], true),
+        // Conversions
+        // Input, destination, radix, number of limbs
+        Opcode::TORADIXLE => (&[M, M, I, I], true),
+    };
+
+    Some(OpcodeSpec {
+        // v1 numbers opcodes by the values they are declared with
+        value: opcode as u8,
+        operands,
+        indirect,
+    })
+}
+
+// The byte value of each opcode in the fixture profile, which leaves 0x05 unassigned
+#[cfg(test)]
+const FIXTURE_OPCODES: &[(Opcode, u8)] = &[
+    // Compute
+    (Opcode::ADD, 0x00),
+    (Opcode::SUB, 0x01),
+    (Opcode::MUL, 0x02),
+    (Opcode::DIV, 0x03),
+    (Opcode::FDIV, 0x04),
+    (Opcode::EQ, 0x06),
+    (Opcode::LT, 0x07),
+    (Opcode::LTE, 0x08),
+    (Opcode::AND, 0x09),
+    (Opcode::OR, 0x0a),
+    (Opcode::XOR, 0x0b),
+    (Opcode::NOT, 0x0c),
+    (Opcode::SHL, 0x0d),
+    (Opcode::SHR, 0x0e),
+    (Opcode::CAST, 0x0f),
+    // Execution environment
+    (Opcode::ADDRESS, 0x10),
+    (Opcode::STORAGEADDRESS, 0x11),
+    (Opcode::SENDER, 0x12),
+    (Opcode::FEEPERL2GAS, 0x13),
+    (Opcode::FEEPERDAGAS, 0x14),
+    (Opcode::TRANSACTIONFEE, 0x15),
+    (Opcode::CONTRACTCALLDEPTH, 0x16),
+    (Opcode::CHAINID, 0x17),
+    (Opcode::VERSION, 0x18),
+    (Opcode::BLOCKNUMBER, 0x19),
+    (Opcode::TIMESTAMP, 0x1a),
+    (Opcode::COINBASE, 0x1b),
+    (Opcode::BLOCKL2GASLIMIT, 0x1c),
+    (Opcode::BLOCKDAGASLIMIT, 0x1d),
+    (Opcode::CALLDATACOPY, 0x1e),
+    // Gas
+    (Opcode::L2GASLEFT, 0x1f),
+    (Opcode::DAGASLEFT, 0x20),
+    // Control flow
+    (Opcode::JUMP, 0x21),
+    (Opcode::JUMPI, 0x22),
+    (Opcode::INTERNALCALL, 0x23),
+    (Opcode::INTERNALRETURN, 0x24),
+    // Memory
+    (Opcode::SET, 0x25),
+    (Opcode::MOV, 0x26),
+    (Opcode::CMOV, 0x27),
+    // World state
+    (Opcode::SLOAD, 0x28),
+    (Opcode::SSTORE, 0x29),
+    (Opcode::NOTEHASHEXISTS, 0x2a),
+    (Opcode::EMITNOTEHASH, 0x2b),
+    (Opcode::NULLIFIEREXISTS, 0x2c),
+    (Opcode::EMITNULLIFIER, 0x2d),
+    (Opcode::L1TOL2MSGEXISTS, 0x2e),
+    (Opcode::HEADERMEMBER, 0x2f),
+    (Opcode::GETCONTRACTINSTANCE, 0x30),
+    (Opcode::EMITUNENCRYPTEDLOG, 0x31),
+    (Opcode::SENDL2TOL1MSG, 0x32),
+    // External calls
+    (Opcode::CALL, 0x33),
+    (Opcode::STATICCALL, 0x34),
+    (Opcode::DELEGATECALL, 0x35),
+    (Opcode::RETURN, 0x36),
+    (Opcode::REVERT, 0x37),
+    // Misc
+    (Opcode::DEBUGLOG, 0x38),
+    // Gadgets
+    (Opcode::KECCAK, 0x39),
+    (Opcode::POSEIDON2, 0x3a),
+    (Opcode::ECADD, 0x3b),
+    (Opcode::MSM, 0x3c),
+    // Conversions
+    (Opcode::TORADIXLE, 0x3d),
+];
+
+#[cfg(test)]
+fn fixture_spec(opcode: Opcode) -> Option<OpcodeSpec> {
+    const M: OperandSpec = OperandSpec::new(OperandKind::Memory, OperandWidth::Bytes(4));
+    const I: OperandSpec = OperandSpec::new(OperandKind::Immediate, OperandWidth::Bytes(4));
+    const J: OperandSpec = OperandSpec::new(OperandKind::JumpTarget, OperandWidth::Bytes(4));
+
+    let &(_, value) = FIXTURE_OPCODES
+        .iter()
+        .find(|&&(candidate, _)| candidate == opcode)?;
+    let (operands, indirect): (&'static [OperandSpec], bool) = match opcode {
+        // Compute
+        Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::DIV
+        | Opcode::FDIV
+        | Opcode::EQ
+        | Opcode::LT
+        | Opcode::LTE
+        | Opcode::AND
+        | Opcode::OR
+        | Opcode::XOR
+        | Opcode::SHL
+        | Opcode::SHR => (&[M, M, M], true),
+        Opcode::NOT => (&[M, M], true),
+        Opcode::CAST => (&[T, M, M], true),
+        // Execution environment
+        Opcode::ADDRESS
+        | Opcode::STORAGEADDRESS
+        | Opcode::SENDER
+        | Opcode::FEEPERL2GAS
+        | Opcode::FEEPERDAGAS
+        | Opcode::TRANSACTIONFEE
+        | Opcode::CONTRACTCALLDEPTH
+        | Opcode::CHAINID
+        | Opcode::VERSION
+        | Opcode::BLOCKNUMBER
+        | Opcode::TIMESTAMP
+        | Opcode::COINBASE
+        | Opcode::BLOCKL2GASLIMIT
+        | Opcode::BLOCKDAGASLIMIT => (&[M], true),
+        // The offset and number of fields are read from memory rather than given inline
+        Opcode::CALLDATACOPY => (&[M, M, M], true),
+        // Gas
+        Opcode::L2GASLEFT | Opcode::DAGASLEFT => (&[M], true),
+        // Control flow
+        Opcode::JUMP | Opcode::INTERNALCALL => (&[J], false),
+        Opcode::JUMPI => (&[J, M], true),
+        Opcode::INTERNALRETURN => (&[], false),
+        // Memory
+        Opcode::SET => (&[T, V, M], true),
+        Opcode::MOV => (&[M, M], true),
+        Opcode::CMOV => (&[M, M, M, M], true),
+        // World state
+        Opcode::SLOAD | Opcode::SSTORE => (&[M, M], true),
+        Opcode::NOTEHASHEXISTS | Opcode::NULLIFIEREXISTS | Opcode::L1TOL2MSGEXISTS => {
+            (&[M, M, M], true)
+        }
+        Opcode::EMITNOTEHASH | Opcode::EMITNULLIFIER => (&[M], true),
+        Opcode::HEADERMEMBER => (&[M, M, M], true),
+        Opcode::GETCONTRACTINSTANCE => (&[M, M], true),
+        Opcode::EMITUNENCRYPTEDLOG | Opcode::SENDL2TOL1MSG => (&[M, M], true),
+        // External calls
+        // Gas, address, args, args size, return data, return size, success, selector
+        Opcode::CALL | Opcode::STATICCALL | Opcode::DELEGATECALL => {
+            (&[M, M, M, M, M, I, M, M], true)
+        }
+        Opcode::RETURN | Opcode::REVERT => (&[M, I], true),
+        // Misc
+        // Message, message size, fields, fields size
+        Opcode::DEBUGLOG => (&[M, I, M, M], true),
+        // Gadgets
+        Opcode::KECCAK => (&[M, M, M], true),
+        Opcode::POSEIDON2 => (&[M, M], true),
+        // Both points as x, y and is infinite, then the destination
+        Opcode::ECADD => (&[M, M, M, M, M, M, M], true),
+        Opcode::MSM => (&[M, M, M, M], true),
+        // Conversions
+        // Input, destination, radix, number of limbs
+        Opcode::TORADIXLE => (&[M, M, I, I], true),
+        Opcode::SHA256 | Opcode::PEDERSEN => return None,
+    };
+
+    Some(OpcodeSpec {
+        value,
+        operands,
+        indirect,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_selected_by_name() {
+        for isa in Isa::ALL {
+            assert_eq!(isa.name().parse::<Isa>(), Ok(isa));
+        }
+        assert_eq!(
+            "v0".parse::<Isa>(),
+            Err("unknown instruction set `v0`, expected one of v1".to_owned())
+        );
+        assert_eq!(
+            "fixture".parse::<Isa>(),
+            Err("unknown instruction set `fixture`, expected one of v1".to_owned())
+        );
+    }
+
+    // Bytecode that has already been emitted depends on these values, changing any of them is a
+    // breaking change to the instruction set
+    #[test]
+    fn opcode_values_are_pinned() {
+        #[rustfmt::skip]
+        let expected: Vec<(Opcode, Option<u8>, Option<u8>)> = vec![
+            // Opcode, v1, fixture
+            (Opcode::ADD, Some(0x00), Some(0x00)),
+            (Opcode::SUB, Some(0x01), Some(0x01)),
+            (Opcode::MUL, Some(0x02), Some(0x02)),
+            (Opcode::DIV, Some(0x03), Some(0x03)),
+            (Opcode::FDIV, Some(0x04), Some(0x04)),
+            (Opcode::EQ, Some(0x05), Some(0x06)),
+            (Opcode::LT, Some(0x06), Some(0x07)),
+            (Opcode::LTE, Some(0x07), Some(0x08)),
+            (Opcode::AND, Some(0x08), Some(0x09)),
+            (Opcode::OR, Some(0x09), Some(0x0a)),
+            (Opcode::XOR, Some(0x0a), Some(0x0b)),
+            (Opcode::NOT, Some(0x0b), Some(0x0c)),
+            (Opcode::SHL, Some(0x0c), Some(0x0d)),
+            (Opcode::SHR, Some(0x0d), Some(0x0e)),
+            (Opcode::CAST, Some(0x0e), Some(0x0f)),
+            (Opcode::ADDRESS, Some(0x0f), Some(0x10)),
+            (Opcode::STORAGEADDRESS, Some(0x10), Some(0x11)),
+            (Opcode::SENDER, Some(0x11), Some(0x12)),
+            (Opcode::FEEPERL2GAS, Some(0x12), Some(0x13)),
+            (Opcode::FEEPERDAGAS, Some(0x13), Some(0x14)),
+            (Opcode::TRANSACTIONFEE, Some(0x14), Some(0x15)),
+            (Opcode::CONTRACTCALLDEPTH, Some(0x15), Some(0x16)),
+            (Opcode::CHAINID, Some(0x16), Some(0x17)),
+            (Opcode::VERSION, Some(0x17), Some(0x18)),
+            (Opcode::BLOCKNUMBER, Some(0x18), Some(0x19)),
+            (Opcode::TIMESTAMP, Some(0x19), Some(0x1a)),
+            (Opcode::COINBASE, Some(0x1a), Some(0x1b)),
+            (Opcode::BLOCKL2GASLIMIT, Some(0x1b), Some(0x1c)),
+            (Opcode::BLOCKDAGASLIMIT, Some(0x1c), Some(0x1d)),
+            (Opcode::CALLDATACOPY, Some(0x1d), Some(0x1e)),
+            (Opcode::L2GASLEFT, Some(0x1e), Some(0x1f)),
+            (Opcode::DAGASLEFT, Some(0x1f), Some(0x20)),
+            (Opcode::JUMP, Some(0x20), Some(0x21)),
+            (Opcode::JUMPI, Some(0x21), Some(0x22)),
+            (Opcode::INTERNALCALL, Some(0x22), Some(0x23)),
+            (Opcode::INTERNALRETURN, Some(0x23), Some(0x24)),
+            (Opcode::SET, Some(0x24), Some(0x25)),
+            (Opcode::MOV, Some(0x25), Some(0x26)),
+            (Opcode::CMOV, Some(0x26), Some(0x27)),
+            (Opcode::SLOAD, Some(0x27), Some(0x28)),
+            (Opcode::SSTORE, Some(0x28), Some(0x29)),
+            (Opcode::NOTEHASHEXISTS, Some(0x29), Some(0x2a)),
+            (Opcode::EMITNOTEHASH, Some(0x2a), Some(0x2b)),
+            (Opcode::NULLIFIEREXISTS, Some(0x2b), Some(0x2c)),
+            (Opcode::EMITNULLIFIER, Some(0x2c), Some(0x2d)),
+            (Opcode::L1TOL2MSGEXISTS, Some(0x2d), Some(0x2e)),
+            (Opcode::HEADERMEMBER, Some(0x2e), Some(0x2f)),
+            (Opcode::GETCONTRACTINSTANCE, Some(0x2f), Some(0x30)),
+            (Opcode::EMITUNENCRYPTEDLOG, Some(0x30), Some(0x31)),
+            (Opcode::SENDL2TOL1MSG, Some(0x31), Some(0x32)),
+            (Opcode::CALL, Some(0x32), Some(0x33)),
+            (Opcode::STATICCALL, Some(0x33), Some(0x34)),
+            (Opcode::DELEGATECALL, Some(0x34), Some(0x35)),
+            (Opcode::RETURN, Some(0x35), Some(0x36)),
+            (Opcode::REVERT, Some(0x36), Some(0x37)),
+            (Opcode::DEBUGLOG, Some(0x37), Some(0x38)),
+            (Opcode::KECCAK, Some(0x38), Some(0x39)),
+            (Opcode::POSEIDON2, Some(0x39), Some(0x3a)),
+            (Opcode::SHA256, Some(0x3a), None),
+            (Opcode::PEDERSEN, Some(0x3b), None),
+            (Opcode::ECADD, Some(0x3c), Some(0x3b)),
+            (Opcode::MSM, Some(0x3d), Some(0x3c)),
+            (Opcode::TORADIXLE, Some(0x3e), Some(0x3d)),
+        ];
+
+        assert_eq!(expected.len(), Opcode::ALL.len());
+        for (opcode, v1, fixture) in expected {
+            let value = |isa: Isa| isa.spec(opcode).map(|spec| spec.value);
+            assert_eq!(value(Isa::V1), v1, "v1 value of {}", opcode.name());
+            assert_eq!(
+                value(Isa::Fixture),
+                fixture,
+                "fixture value of {}",
+                opcode.name()
+            );
+        }
+    }
+
+    #[test]
+    fn opcodes_round_trip_through_their_value() {
+        for opcode in Opcode::ALL {
+            assert_eq!(Opcode::try_from(opcode as u8), Ok(opcode));
+        }
+        assert_eq!(
+            Opcode::try_from(0xff),
+            Err("unknown opcode `0xff`".to_owned())
+        );
+    }
+
+    #[test]
+    fn opcode_values_are_unique_within_a_profile() {
+        for isa in [Isa::V1, Isa::Fixture] {
+            let mut values: Vec<u8> = crate::opcodes::OPCODE_MAP
+                .values()
+                .filter_map(|&opcode| isa.spec(opcode))
+                .map(|spec| spec.value)
+                .collect();
+            let count = values.len();
+            values.sort();
+            values.dedup();
+            assert_eq!(values.len(), count, "duplicate opcode value in {isa}");
+        }
+    }
+}
@@ -1,7 +1,27 @@
+pub mod artifact;
+pub mod assembler;
 mod codegen;
 pub mod compiler;
+// The debugger reads programs and their sources from disk, which the browser cannot do
+#[cfg(not(target_arch = "wasm32"))]
+pub mod debugger;
+pub mod diagnostic;
+pub mod disassembler;
 mod fm;
+pub mod host;
 mod instruction;
+pub mod interpreter;
+pub mod isa;
+pub mod loader;
 mod opcodes;
 mod parser;
+pub mod render;
+pub mod source_map;
+mod span;
 mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use instruction::Instruction;
+pub use opcodes::Opcode;
+pub use parser::{BinaryOperator, Operand, TypeTag};
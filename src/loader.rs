@@ -0,0 +1,122 @@
+// Where source files are read from
+//
+// The assembler reads the file it is given, and every file that includes, through a `FileLoader`.
+// `FsLoader` reads from disk, and so is left out of wasm builds, while `MemoryLoader` serves files from a map so that programs can be
+// assembled from an editor buffer, in the browser or in tests without touching the file system.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Reads the files a program is assembled from.
+pub trait FileLoader {
+    /// The contents of a file.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// A path that is the same however the file is referred to, failing if there is no such
+    /// file. Files are only included once, so this decides whether two includes are the same.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+}
+
+/// Reads files from disk. Not available in the browser, which has no file system to read.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsLoader;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileLoader for FsLoader {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+}
+
+/// Serves files from memory, keyed by their path.
+///
+/// Paths are compared once `.` and `..` have been resolved, so `lib/../main.avm` is `main.avm`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: impl Into<String>) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    pub fn with_file(mut self, path: impl AsRef<Path>, contents: impl Into<String>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+}
+
+impl<P: AsRef<Path>, S: Into<String>> FromIterator<(P, S)> for MemoryLoader {
+    fn from_iter<I: IntoIterator<Item = (P, S)>>(files: I) -> Self {
+        let mut loader = MemoryLoader::new();
+        for (path, contents) in files {
+            loader.insert(path, contents);
+        }
+        loader
+    }
+}
+
+impl FileLoader for MemoryLoader {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        match self.files.contains_key(&path) {
+            true => Ok(path),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        }
+    }
+}
+
+// Resolve `.` and `..` without consulting the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_paths_are_normalized() {
+        let loader: MemoryLoader = [("./lib/math.avm", "add 0 1 2;")].into_iter().collect();
+
+        for path in ["lib/math.avm", "lib/../lib/./math.avm"] {
+            assert_eq!(loader.read(Path::new(path)).unwrap(), "add 0 1 2;");
+            assert_eq!(
+                loader.canonicalize(Path::new(path)).unwrap(),
+                Path::new("lib/math.avm")
+            );
+        }
+        assert!(loader.read(Path::new("math.avm")).is_err());
+        assert_eq!(normalize(Path::new("../a/../../b")), Path::new("../../b"));
+    }
+}
@@ -1,25 +1,263 @@
-use avm_asm::compiler::compile_file;
-use clap::Parser;
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use avm_asm::{
+    assembler::Assembler,
+    compiler::{compile_contract, compile_file_with_options, CompileOptions, OutputFormat},
+    debugger::debug,
+    diagnostic::Diagnostic,
+    disassembler::{disassemble_bytes, read_bytecode},
+    host::InMemoryHost,
+    interpreter::execute,
+    isa::Isa,
+    loader::FsLoader,
+    render::render,
+};
+use clap::{Parser, Subcommand};
+use num_bigint::BigUint;
+use num_traits::Num;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(name = "avm-asm", version = "0.1.0", author = "Maddiaa")]
 struct AvmAsm {
     pub path: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Directories to search for included files
+    #[clap(short = 'I', long = "include-path", global = true)]
+    pub include_paths: Vec<PathBuf>,
+
+    /// Define a constant, e.g. `-D slot=0x2a`
+    #[clap(short = 'D', long = "define", global = true, value_parser = parse_define)]
+    pub defines: Vec<(String, BigUint)>,
+
+    /// How deeply macros may invoke other macros
+    #[clap(long, global = true, default_value_t = CompileOptions::default().max_expansion_depth)]
+    pub max_expansion_depth: usize,
+
+    /// Write the bytecode to this file rather than to stdout
+    #[clap(short = 'o', long = "output")]
+    pub output: Option<PathBuf>,
+
+    /// How to write the bytecode, one of hex, lowercase-hex, prefixed-hex, base64 or binary
+    #[clap(long, default_value_t = OutputFormat::default())]
+    pub format: OutputFormat,
+
+    /// Also write JSON debug info, mapping each instruction back to its source, to this file
+    #[clap(long)]
+    pub debug_info: Option<PathBuf>,
+
+    /// The instruction set revision to assemble for, currently only v1
+    #[clap(long, global = true, default_value_t = Isa::default())]
+    pub isa: Isa,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Turn bytecode back into assembly
+    Disasm {
+        /// A file containing bytecode, or hex encoded bytecode itself
+        input: String,
+
+        /// How the bytecode is written, one of the output formats. Detected if not given
+        #[clap(long)]
+        input_format: Option<OutputFormat>,
+    },
+    /// Assemble a program and run it with the reference interpreter
+    Run {
+        /// The assembly file to run
+        path: String,
+
+        /// A JSON file seeding the environment, calldata and world state the program runs against
+        #[clap(long)]
+        fixture: Option<PathBuf>,
+    },
+    /// Compile each entry point of a program into a JSON contract artifact
+    Contract {
+        /// The assembly file to compile
+        path: String,
+
+        /// Write the artifact to this file rather than to stdout
+        #[clap(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+    },
+    /// Step through a program with the reference interpreter
+    Debug {
+        /// The assembly file to debug
+        path: String,
+
+        /// A JSON file seeding the environment, calldata and world state the program runs against
+        #[clap(long)]
+        fixture: Option<PathBuf>,
+    },
 }
 
 fn main() {
     let cli = AvmAsm::parse();
 
-    // Check if no argument is provided
-    if cli.path.is_none() {
-        println!("No path provided! Use --help for more information.");
-        return;
+    match &cli.command {
+        Some(Command::Disasm {
+            input,
+            input_format,
+        }) => {
+            // Read the bytecode from a file, unless there is no such file and it was given inline
+            let input = match std::fs::read(input) {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == ErrorKind::NotFound && is_inline_hex(input) => {
+                    input.clone().into_bytes()
+                }
+                Err(error) => fail(format!("could not read {input}: {error}")),
+            };
+            let bytecode = read_bytecode(&input, *input_format).unwrap_or_else(|error| fail(error));
+            match disassemble_bytes(&bytecode, cli.isa) {
+                Ok(assembly) => print!("{assembly}"),
+                Err(error) => fail(error),
+            }
+        }
+        Some(Command::Run { path, fixture }) => {
+            let bytecode = compile(path, &cli);
+            let mut host = load_host(fixture);
+            match execute(&bytecode, cli.isa, &mut host) {
+                Ok(outcome) if outcome.reverted => {
+                    eprintln!("reverted with {:?}", outcome.output);
+                    std::process::exit(1);
+                }
+                Ok(outcome) => {
+                    for value in outcome.output {
+                        println!("{value}");
+                    }
+                }
+                Err(error) => fail(error),
+            }
+        }
+        Some(Command::Contract { path, output }) => {
+            let artifact = compile_contract(path, &options(&cli), &FsLoader)
+                .unwrap_or_else(|diagnostics| report(&diagnostics));
+            match output {
+                Some(output) => write(output, artifact.to_json().as_bytes()),
+                None => println!("{}", artifact.to_json()),
+            }
+        }
+        Some(Command::Debug { path, fixture }) => {
+            let mut host = load_host(fixture);
+            let stdin = std::io::stdin();
+            if let Err(diagnostics) = debug(
+                path,
+                &options(&cli),
+                &mut host,
+                stdin.lock(),
+                std::io::stdout(),
+            ) {
+                report(&diagnostics);
+            }
+        }
+        // Check if no argument is provided
+        None => match &cli.path {
+            Some(path) => assemble(path, &cli),
+            None => println!("No path provided! Use --help for more information."),
+        },
     }
+}
 
-    // Read the file
-    let path = cli.path.unwrap();
+fn fail(error: impl std::fmt::Display) -> ! {
+    eprintln!("error: {error}");
+    std::process::exit(1);
+}
 
-    let bytecode = compile_file(&path);
+// Assemble the file at `path`, writing the bytecode and any debug info out
+fn assemble(path: &String, cli: &AvmAsm) {
+    let mut assembler = Assembler::new()
+        .isa(cli.isa)
+        .max_expansion_depth(cli.max_expansion_depth)
+        .format(cli.format);
+    for include_path in &cli.include_paths {
+        assembler = assembler.include_path(include_path);
+    }
+    for (name, value) in &cli.defines {
+        assembler = assembler.define(name, value.clone());
+    }
+
+    let program = assembler
+        .assemble_file(path)
+        .unwrap_or_else(|diagnostics| report(&diagnostics));
+    print_diagnostics(&program.warnings);
+    if let Some(debug_info_path) = &cli.debug_info {
+        write(debug_info_path, program.source_map.to_json().as_bytes());
+    }
 
-    println!("{bytecode}");
+    match &cli.output {
+        Some(output) => write(output, &program.bytes),
+        None if cli.format.is_text() => println!("{}", String::from_utf8_lossy(&program.bytes)),
+        None => {
+            let _ = std::io::stdout().write_all(&program.bytes);
+        }
+    }
+}
+
+// A constant given on the command line as `name=value`, in decimal or hex
+fn parse_define(define: &str) -> Result<(String, BigUint), String> {
+    let (name, value) = define
+        .split_once('=')
+        .ok_or_else(|| format!("`{define}` should be written as name=value"))?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => BigUint::from_str_radix(hex, 16),
+        None => BigUint::from_str_radix(value, 10),
+    };
+    let value = parsed.map_err(|_| format!("`{value}` is not a number"))?;
+    Ok((name.to_owned(), value))
+}
+
+fn write(path: &PathBuf, contents: &[u8]) {
+    std::fs::write(path, contents)
+        .unwrap_or_else(|error| fail(format!("could not write {}: {error}", path.display())));
+}
+
+fn options(cli: &AvmAsm) -> CompileOptions {
+    CompileOptions {
+        max_expansion_depth: cli.max_expansion_depth,
+        include_paths: cli.include_paths.clone(),
+        isa: cli.isa,
+        defines: cli.defines.iter().cloned().collect::<BTreeMap<_, _>>(),
+    }
+}
+
+// Assemble the file at `path`, exiting with its diagnostics if it has errors
+fn compile(path: &String, cli: &AvmAsm) -> String {
+    compile_file_with_options(path, &options(cli))
+        .unwrap_or_else(|diagnostics| report(&diagnostics))
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    print_diagnostics(diagnostics);
+    std::process::exit(1);
+}
+
+fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    // Only colour output for humans, CI logs get plain text
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    for diagnostic in diagnostics {
+        let source = |file: &str| std::fs::read_to_string(file).ok();
+        eprintln!("{}", render(diagnostic, source, color));
+    }
+}
+
+// The world state to run against, seeded from a fixture if one was given
+// Whether a `disasm` argument is hex bytecode rather than a path
+fn is_inline_hex(input: &str) -> bool {
+    input.starts_with("0x") || (!input.is_empty() && input.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn load_host(fixture: &Option<PathBuf>) -> InMemoryHost {
+    match fixture {
+        Some(fixture) => std::fs::read_to_string(fixture)
+            .map_err(|error| format!("could not read {}: {error}", fixture.display()))
+            .and_then(|json| InMemoryHost::from_json(&json))
+            .unwrap_or_else(|error| fail(error)),
+        None => InMemoryHost::default(),
+    }
 }
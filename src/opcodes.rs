@@ -1,86 +1,168 @@
 use phf::phf_map;
 
+use crate::isa::Isa;
+
 /// All  opcodes
 /// Keep updated with TS, cpp, and docs protocol specs!
+/// Each value is the opcode's byte in v1 of the instruction set, so must never change once
+/// released. Other instruction sets number opcodes themselves, see `isa`
 #[allow(clippy::upper_case_acronyms, dead_code)]
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[repr(u8)]
 pub enum Opcode {
     // Compute
-    ADD,
-    SUB,
-    MUL,
-    DIV,
-    FDIV,
-    EQ,
-    LT,
-    LTE,
-    AND,
-    OR,
-    XOR,
-    NOT,
-    SHL,
-    SHR,
-    CAST,
+    ADD = 0x00,
+    SUB = 0x01,
+    MUL = 0x02,
+    DIV = 0x03,
+    FDIV = 0x04,
+    EQ = 0x05,
+    LT = 0x06,
+    LTE = 0x07,
+    AND = 0x08,
+    OR = 0x09,
+    XOR = 0x0a,
+    NOT = 0x0b,
+    SHL = 0x0c,
+    SHR = 0x0d,
+    CAST = 0x0e,
     // Execution environment
-    ADDRESS,
-    STORAGEADDRESS,
-    SENDER,
-    FEEPERL2GAS,
-    FEEPERDAGAS,
-    TRANSACTIONFEE,
-    CONTRACTCALLDEPTH,
-    CHAINID,
-    VERSION,
-    BLOCKNUMBER,
-    TIMESTAMP,
-    COINBASE,
-    BLOCKL2GASLIMIT,
-    BLOCKDAGASLIMIT,
-    CALLDATACOPY,
+    ADDRESS = 0x0f,
+    STORAGEADDRESS = 0x10,
+    SENDER = 0x11,
+    FEEPERL2GAS = 0x12,
+    FEEPERDAGAS = 0x13,
+    TRANSACTIONFEE = 0x14,
+    CONTRACTCALLDEPTH = 0x15,
+    CHAINID = 0x16,
+    VERSION = 0x17,
+    BLOCKNUMBER = 0x18,
+    TIMESTAMP = 0x19,
+    COINBASE = 0x1a,
+    BLOCKL2GASLIMIT = 0x1b,
+    BLOCKDAGASLIMIT = 0x1c,
+    CALLDATACOPY = 0x1d,
     // Gas
-    L2GASLEFT,
-    DAGASLEFT,
+    L2GASLEFT = 0x1e,
+    DAGASLEFT = 0x1f,
     // Control flow
-    JUMP,
-    JUMPI,
-    INTERNALCALL,
-    INTERNALRETURN,
+    JUMP = 0x20,
+    JUMPI = 0x21,
+    INTERNALCALL = 0x22,
+    INTERNALRETURN = 0x23,
     // Memory
-    SET,
-    MOV,
-    CMOV,
+    SET = 0x24,
+    MOV = 0x25,
+    CMOV = 0x26,
     // World state
-    SLOAD,
-    SSTORE,
-    NOTEHASHEXISTS,
-    EMITNOTEHASH,
-    NULLIFIEREXISTS,
-    EMITNULLIFIER,
-    L1TOL2MSGEXISTS,
-    HEADERMEMBER,
-    GETCONTRACTINSTANCE,
-    EMITUNENCRYPTEDLOG,
-    SENDL2TOL1MSG,
+    SLOAD = 0x27,
+    SSTORE = 0x28,
+    NOTEHASHEXISTS = 0x29,
+    EMITNOTEHASH = 0x2a,
+    NULLIFIEREXISTS = 0x2b,
+    EMITNULLIFIER = 0x2c,
+    L1TOL2MSGEXISTS = 0x2d,
+    HEADERMEMBER = 0x2e,
+    GETCONTRACTINSTANCE = 0x2f,
+    EMITUNENCRYPTEDLOG = 0x30,
+    SENDL2TOL1MSG = 0x31,
     // External calls
-    CALL,
-    STATICCALL,
-    DELEGATECALL,
-    RETURN,
-    REVERT,
+    CALL = 0x32,
+    STATICCALL = 0x33,
+    DELEGATECALL = 0x34,
+    RETURN = 0x35,
+    REVERT = 0x36,
     // Misc
-    DEBUGLOG,
+    DEBUGLOG = 0x37,
     // Gadgets
-    KECCAK,
-    POSEIDON2,
-    SHA256,   // temp - may be removed, but alot of contracts rely on it
-    PEDERSEN, // temp - may be removed, but alot of contracts rely on it
-    ECADD,
-    MSM,
+    KECCAK = 0x38,
+    POSEIDON2 = 0x39,
+    SHA256 = 0x3a,   // temp - may be removed, but alot of contracts rely on it
+    PEDERSEN = 0x3b, // temp - may be removed, but alot of contracts rely on it
+    ECADD = 0x3c,
+    MSM = 0x3d,
     // Conversions
-    TORADIXLE,
+    TORADIXLE = 0x3e,
+}
+
+/// Decodes a v1 opcode byte. Use `Isa::opcode` for other instruction sets.
+impl TryFrom<u8> for Opcode {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Isa::V1
+            .opcode(value)
+            .ok_or_else(|| format!("unknown opcode `{value:#04x}`"))
+    }
 }
 
 impl Opcode {
+    pub const ALL: [Opcode; 63] = [
+        Opcode::ADD,
+        Opcode::SUB,
+        Opcode::MUL,
+        Opcode::DIV,
+        Opcode::FDIV,
+        Opcode::EQ,
+        Opcode::LT,
+        Opcode::LTE,
+        Opcode::AND,
+        Opcode::OR,
+        Opcode::XOR,
+        Opcode::NOT,
+        Opcode::SHL,
+        Opcode::SHR,
+        Opcode::CAST,
+        Opcode::ADDRESS,
+        Opcode::STORAGEADDRESS,
+        Opcode::SENDER,
+        Opcode::FEEPERL2GAS,
+        Opcode::FEEPERDAGAS,
+        Opcode::TRANSACTIONFEE,
+        Opcode::CONTRACTCALLDEPTH,
+        Opcode::CHAINID,
+        Opcode::VERSION,
+        Opcode::BLOCKNUMBER,
+        Opcode::TIMESTAMP,
+        Opcode::COINBASE,
+        Opcode::BLOCKL2GASLIMIT,
+        Opcode::BLOCKDAGASLIMIT,
+        Opcode::CALLDATACOPY,
+        Opcode::L2GASLEFT,
+        Opcode::DAGASLEFT,
+        Opcode::JUMP,
+        Opcode::JUMPI,
+        Opcode::INTERNALCALL,
+        Opcode::INTERNALRETURN,
+        Opcode::SET,
+        Opcode::MOV,
+        Opcode::CMOV,
+        Opcode::SLOAD,
+        Opcode::SSTORE,
+        Opcode::NOTEHASHEXISTS,
+        Opcode::EMITNOTEHASH,
+        Opcode::NULLIFIEREXISTS,
+        Opcode::EMITNULLIFIER,
+        Opcode::L1TOL2MSGEXISTS,
+        Opcode::HEADERMEMBER,
+        Opcode::GETCONTRACTINSTANCE,
+        Opcode::EMITUNENCRYPTEDLOG,
+        Opcode::SENDL2TOL1MSG,
+        Opcode::CALL,
+        Opcode::STATICCALL,
+        Opcode::DELEGATECALL,
+        Opcode::RETURN,
+        Opcode::REVERT,
+        Opcode::DEBUGLOG,
+        Opcode::KECCAK,
+        Opcode::POSEIDON2,
+        Opcode::SHA256,
+        Opcode::PEDERSEN,
+        Opcode::ECADD,
+        Opcode::MSM,
+        Opcode::TORADIXLE,
+    ];
+
     pub fn name(&self) -> &'static str {
         match self {
             // Compute
@@ -165,7 +247,7 @@ impl Opcode {
             // Gadgets
             Opcode::KECCAK => "KECCAK",
             Opcode::POSEIDON2 => "POSEIDON2",
-            Opcode::SHA256 => "SHA256 ",
+            Opcode::SHA256 => "SHA256",
             Opcode::PEDERSEN => "PEDERSEN",
             Opcode::ECADD => "ECADD",
             Opcode::MSM => "MSM",
@@ -184,7 +266,7 @@ pub static OPCODE_MAP: phf::Map<&'static str, Opcode> = phf_map! {
     "sub" => Opcode::SUB,
     "mul" => Opcode::MUL,
     "div" => Opcode::DIV,
-    "fdiV" => Opcode::FDIV,
+    "fdiv" => Opcode::FDIV,
     "eq" => Opcode::EQ,
     "lt" => Opcode::LT,
     "lte" => Opcode::LTE,
@@ -1,66 +1,183 @@
+use std::{fmt, rc::Rc};
+
 use lalrpop_util::*;
+use num_bigint::BigUint;
+use num_traits::{Num, ToPrimitive, Zero};
 
 pub mod types;
 
-use crate::{opcodes::Opcode, utils::hex_to_bytes};
+use crate::{diagnostic::Diagnostic, opcodes::Opcode, span::Spanned, utils::biguint_to_bytes};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum Statement {
-    IncludeStatement(String),
-    MacroStatement(String, Vec<Statement>),
-    MacroInvocation(String),
+    IncludeStatement(String, /*Namespace*/ Option<String>),
+    MacroStatement(String, Vec<MacroParameter>, Vec<Spanned<Statement>>),
+    MacroInvocation(String, Vec<Spanned<MacroArgument>>),
     OpcodeStatement(
         Opcode,
         /*indirect=*/ bool,
-        Vec<Operand>,
-        /*Label*/ Option<String>,
+        Vec<Spanned<Operand>>,
+        /*Label*/ Option<Spanned<String>>,
     ), // Opcode and it's operands
-    ConstantDefinition(String, Operand),
+    ConstantDefinition(String, Spanned<Operand>),
     Label(String),
+    // The label calls are routed to, and the signature its selector is computed from
+    EntryPoint(String, String),
 }
 
 #[derive(Debug, Clone)]
+pub struct MacroParameter {
+    pub name: Spanned<String>,
+    pub kind: ParameterKind,
+}
+
+// What a macro parameter may be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    Operand,
+    Label,
+}
+
+impl ParameterKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "operand" => Some(ParameterKind::Operand),
+            "label" => Some(ParameterKind::Label),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MacroArgument {
+    Operand(Operand),
+    Label(String),
+}
+
+impl MacroArgument {
+    pub fn kind(&self) -> ParameterKind {
+        match self {
+            MacroArgument::Operand(_) => ParameterKind::Operand,
+            MacroArgument::Label(_) => ParameterKind::Label,
+        }
+    }
+}
+
+/// An operand of an instruction, as written in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operand {
     Decimal(u64),
     Hex(String),
     // TOOD: change name of above to fit that this is now just generic operands not literals
     Tag(TypeTag),
     Variable(String),
+    // Evaluated once every variable within it has been resolved
+    Expression(BinaryOperator, Box<Operand>, Box<Operand>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+}
+
+// Shifting further than this cannot produce a value that fits in any operand
+const MAX_SHIFT: usize = 256;
+
+impl BinaryOperator {
+    pub fn apply(self, lhs: BigUint, rhs: BigUint) -> Result<BigUint, String> {
+        match self {
+            BinaryOperator::Add => Ok(lhs + rhs),
+            BinaryOperator::Sub if lhs < rhs => Err(format!("`{lhs} - {rhs}` underflows")),
+            BinaryOperator::Sub => Ok(lhs - rhs),
+            BinaryOperator::Mul => Ok(lhs * rhs),
+            BinaryOperator::Div | BinaryOperator::Rem if rhs.is_zero() => {
+                Err(format!("`{lhs} {self} 0` divides by zero"))
+            }
+            BinaryOperator::Div => Ok(lhs / rhs),
+            BinaryOperator::Rem => Ok(lhs % rhs),
+            BinaryOperator::Shl => match rhs.to_usize() {
+                Some(shift) if shift <= MAX_SHIFT => Ok(lhs << shift),
+                _ => Err(format!("shift amount `{rhs}` is too large")),
+            },
+            BinaryOperator::Shr => Ok(rhs.to_usize().map_or(BigUint::zero(), |shift| lhs >> shift)),
+            BinaryOperator::And => Ok(lhs & rhs),
+            BinaryOperator::Or => Ok(lhs | rhs),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Rem => "%",
+            BinaryOperator::Shl => "<<",
+            BinaryOperator::Shr => ">>",
+            BinaryOperator::And => "&",
+            BinaryOperator::Or => "|",
+        };
+        write!(f, "{symbol}")
+    }
 }
 
 impl Operand {
-    pub fn to_be_bytes(&self) -> Vec<u8> {
+    // Replace each variable, including those nested within expressions, for which `f` returns a
+    // value
+    pub(crate) fn substitute_variables(&mut self, f: &mut impl FnMut(&str) -> Option<Operand>) {
         match self {
-            Operand::Decimal(value) => value.to_be_bytes().to_vec(),
-            Operand::Hex(value) => {
-                let value = u64::from_str_radix(&value[2..], 16).unwrap();
-                value.to_be_bytes().to_vec()
+            Operand::Variable(name) => {
+                if let Some(value) = f(name) {
+                    *self = value;
+                }
+            }
+            Operand::Expression(_, lhs, rhs) => {
+                lhs.substitute_variables(f);
+                rhs.substitute_variables(f);
             }
-            Operand::Tag(tag) => (tag.clone() as u8).to_be_bytes().to_vec(),
-            _ => panic!("A variable should never appear in the final code!"),
+            _ => {}
         }
     }
 
-    pub fn to_be_bytes_with_hint(&self, tag_hint: TypeTag) -> Vec<u8> {
+    // The numeric value of the operand, with arbitrary precision
+    pub fn evaluate(&self) -> Result<BigUint, String> {
         match self {
-            Operand::Hex(hex_str) => {
-                let number_of_bits = match tag_hint {
-                    TypeTag::U8 => 8,
-                    TypeTag::U16 => 16,
-                    TypeTag::U32 => 32,
-                    TypeTag::U64 => 64,
-                    TypeTag::U128 => 128,
-                    TypeTag::FF => 256,
-                };
-
-                hex_to_bytes(hex_str, number_of_bits)
+            Operand::Decimal(value) => Ok(BigUint::from(*value)),
+            Operand::Hex(value) => BigUint::from_str_radix(&value[2..], 16)
+                .map_err(|_| format!("invalid hex literal `{value}`")),
+            Operand::Tag(_) => Err("type tags cannot be used in expressions".to_owned()),
+            Operand::Variable(name) => Err(format!("constant `${name}` was never resolved")),
+            Operand::Expression(operator, lhs, rhs) => {
+                operator.apply(lhs.evaluate()?, rhs.evaluate()?)
             }
-            _ => self.to_be_bytes(),
+        }
+    }
+
+    // Encode the operand's value in the given number of bits
+    pub(crate) fn to_be_bytes(&self, bit_length: usize) -> Result<Vec<u8>, String> {
+        match self {
+            // Leading zeros don't count towards the width, only the value does
+            Operand::Hex(hex_str) => biguint_to_bytes(&self.evaluate()?, bit_length)
+                .map_err(|_| format!("hex literal `{hex_str}` does not fit in {bit_length} bits")),
+            Operand::Tag(tag) => Err(format!("type tag `{tag}` cannot be used as a value")),
+            _ => biguint_to_bytes(&self.evaluate()?, bit_length),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// The type of a value in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TypeTag {
     U8,
     U16,
@@ -70,14 +187,88 @@ pub enum TypeTag {
     FF,
 }
 
+impl fmt::Display for TypeTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TypeTag::U8 => "u8",
+            TypeTag::U16 => "u16",
+            TypeTag::U32 => "u32",
+            TypeTag::U64 => "u64",
+            TypeTag::U128 => "u128",
+            TypeTag::FF => "ff",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TypeTag {
+    pub fn bits(&self) -> usize {
+        match self {
+            TypeTag::U8 => 8,
+            TypeTag::U16 => 16,
+            TypeTag::U32 => 32,
+            TypeTag::U64 => 64,
+            TypeTag::U128 => 128,
+            TypeTag::FF => 256,
+        }
+    }
+}
+
 // TODO(md): the parser should not be concerned with the file manager, move this up a level
-pub(crate) fn parse_asm(input: String) -> Vec<Statement> {
+pub(crate) fn parse_asm(
+    input: &str,
+    file: &str,
+) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
     let parser = avm::StatementsParser::new();
-    let parsed = parser.parse(&input);
-    parsed.unwrap()
+
+    match parser.parse(&Rc::from(file), &mut errors, input) {
+        Ok(parsed) if errors.is_empty() => Ok(parsed),
+        Ok(_) => Err(errors),
+        Err(error) => {
+            errors.push(syntax_error(file, error));
+            Err(errors)
+        }
+    }
 }
 
-lalrpop_mod!(avm);
+// Convert a lalrpop parse error into a diagnostic
+pub(crate) fn syntax_error<T: fmt::Display, E: fmt::Display>(
+    file: &str,
+    error: ParseError<usize, T, E>,
+) -> Diagnostic {
+    let with_expected = |diagnostic: Diagnostic, expected: &[String]| match expected {
+        [] => diagnostic,
+        [one] => diagnostic.with_note(format!("expected {one}")),
+        many => diagnostic.with_note(format!("expected one of {}", many.join(", "))),
+    };
+
+    match error {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error(file, location..location, "invalid token")
+        }
+        ParseError::UnrecognizedEof { location, expected } => with_expected(
+            Diagnostic::error(file, location..location, "unexpected end of file"),
+            &expected,
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected,
+        } => with_expected(
+            Diagnostic::error(file, start..end, format!("unexpected `{token}`")),
+            &expected,
+        ),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => Diagnostic::error(file, start..end, format!("unexpected `{token}`")),
+        ParseError::User { error } => Diagnostic::error(file, 0..0, error.to_string()),
+    }
+}
+
+lalrpop_mod!(
+    #[allow(clippy::all)]
+    avm
+);
 
 #[test]
 fn test_parser() {
@@ -85,21 +276,146 @@ fn test_parser() {
         add 1 2 3;
     some_label:
         sub 1 2 3;
-    "
-    .to_owned();
+    ";
+
+    parse_asm(input, "test.avm").unwrap();
+}
+
+#[test]
+fn test_parser_macro_parameters() {
+    let input = "
+        .macro goto(cond, target: label) {
+            jumpi @target $cond;
+        };
+        $goto(1, @end);
+    ";
+    let parsed = parse_asm(input, "test.avm").unwrap();
+
+    let Statement::MacroStatement(name, parameters, _) = &parsed[0].node else {
+        panic!("expected a macro definition");
+    };
+    assert_eq!(name, "goto");
+    let kinds: Vec<ParameterKind> = parameters.iter().map(|p| p.kind).collect();
+    assert_eq!(kinds, vec![ParameterKind::Operand, ParameterKind::Label]);
 
-    parse_asm(input);
+    let Statement::MacroInvocation(_, arguments) = &parsed[1].node else {
+        panic!("expected a macro invocation");
+    };
+    assert!(matches!(
+        arguments[0].node,
+        MacroArgument::Operand(Operand::Decimal(1))
+    ));
+    assert!(matches!(&arguments[1].node, MacroArgument::Label(label) if label == "end"));
 }
 
-// #[test]
-// fn test_includes_io() {
-//     // Test includes IO
-//     let input = std::fs::read_to_string("./test_programs/includes.avm").unwrap();
-//     let parsed = parse_asm(input);
+#[test]
+fn test_parser_unknown_parameter_type() {
+    let errors = parse_asm(".macro m(a: string) { add $a 1 2; };", "test.avm").unwrap_err();
+    assert_eq!(errors[0].message, "unknown parameter type `string`");
+}
 
-//     // Each file contains just one macro, so we expect that they end up pointing at the same thing
-//     let expected_length = 2;
-//     assert_eq!(parsed.len(), expected_length);
-// }
+#[test]
+fn test_parser_namespaces() {
+    let input = "
+        .include \"lib/math.avm\" as math;
+        $math::add_u64(1, $math::one);
+    ";
+    let parsed = parse_asm(input, "test.avm").unwrap();
+
+    assert!(matches!(
+        &parsed[0].node,
+        Statement::IncludeStatement(path, Some(namespace)) if path == "lib/math.avm" && namespace == "math"
+    ));
+    let Statement::MacroInvocation(name, arguments) = &parsed[1].node else {
+        panic!("expected a macro invocation");
+    };
+    assert_eq!(name, "math::add_u64");
+    assert!(matches!(
+        &arguments[1].node,
+        MacroArgument::Operand(Operand::Variable(constant)) if constant == "math::one"
+    ));
+}
+
+#[test]
+fn test_parser_entry_points() {
+    let input = ".entry transfer(Field, u32, (ff, AztecAddress));";
+    let parsed = parse_asm(input, "test.avm").unwrap();
+
+    let Statement::EntryPoint(label, signature) = &parsed[0].node else {
+        panic!("expected an entry point");
+    };
+    assert_eq!(label, "transfer");
+    assert_eq!(signature, "transfer(Field,u32,(ff,AztecAddress))");
+}
+
+#[test]
+fn test_parser_spans() {
+    let input = "add 1 $two 3;\nfoo:\n    jump @foo;";
+    let parsed = parse_asm(input, "test.avm").unwrap();
+
+    let text: Vec<&str> = parsed.iter().map(|s| &input[s.span.range()]).collect();
+    assert_eq!(text, vec!["add 1 $two 3", "foo:", "jump @foo"]);
+    assert_eq!(&*parsed[0].span.file, "test.avm");
+
+    let Statement::OpcodeStatement(_, _, operands, None) = &parsed[0].node else {
+        panic!("expected an opcode statement");
+    };
+    assert_eq!(&input[operands[1].span.range()], "$two");
+
+    let Statement::OpcodeStatement(_, _, _, Some(label)) = &parsed[2].node else {
+        panic!("expected a jump to a label");
+    };
+    assert_eq!(&input[label.span.range()], "@foo");
+}
+
+#[test]
+fn test_parser_expressions() {
+    let input = "add 1 + 2 * 3 ($A - 1) << 2 | 1;";
+    let parsed = parse_asm(input, "test.avm").unwrap();
+
+    let Statement::OpcodeStatement(_, _, operands, None) = &parsed[0].node else {
+        panic!("expected an opcode statement");
+    };
+    let text: Vec<&str> = operands.iter().map(|o| &input[o.span.range()]).collect();
+    assert_eq!(text, vec!["1 + 2 * 3", "($A - 1) << 2 | 1"]);
+
+    // Multiplication binds tighter than addition
+    let Operand::Expression(BinaryOperator::Add, _, rhs) = &operands[0].node else {
+        panic!("expected an addition");
+    };
+    assert!(matches!(
+        **rhs,
+        Operand::Expression(BinaryOperator::Mul, ..)
+    ));
+
+    // Shifts bind tighter than bitwise or
+    let Operand::Expression(BinaryOperator::Or, lhs, _) = &operands[1].node else {
+        panic!("expected a bitwise or");
+    };
+    assert!(matches!(
+        **lhs,
+        Operand::Expression(BinaryOperator::Shl, ..)
+    ));
+}
+
+#[test]
+fn test_parser_reports_all_errors() {
+    let input = "
+        add 1 2 3;
+        frobnicate 1 2;
+        sub 1 2 3 {;
+        wibble;
+    ";
+
+    let errors = parse_asm(input, "test.avm").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+
+    assert_eq!(errors.len(), 3);
+    assert_eq!(messages[0], "unknown opcode `frobnicate`");
+    assert_eq!(messages[1], "unexpected `{`");
+    assert!(errors[1].notes[0].starts_with("expected one of"));
+    assert_eq!(messages[2], "unknown opcode `wibble`");
+    assert_eq!(&input[errors[0].span.clone()], "frobnicate");
+}
 
 // Next test: make labels work in the multi file setting
@@ -60,16 +60,18 @@ impl From<String> for Operand {
 }
 
 // Type tag
-impl From<u8> for TypeTag {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for TypeTag {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => TypeTag::U8,
-            1 => TypeTag::U16,
-            2 => TypeTag::U32,
-            3 => TypeTag::U64,
-            4 => TypeTag::U128,
-            5 => TypeTag::FF,
-            _ => panic!("Invalid type tag"),
+            0 => Ok(TypeTag::U8),
+            1 => Ok(TypeTag::U16),
+            2 => Ok(TypeTag::U32),
+            3 => Ok(TypeTag::U64),
+            4 => Ok(TypeTag::U128),
+            5 => Ok(TypeTag::FF),
+            _ => Err(format!("invalid type tag `{value}`")),
         }
     }
 }
@@ -0,0 +1,240 @@
+// Render diagnostics in the style of rustc, with the offending source line underlined
+//
+// error: label `nowhere` is not defined
+//  --> main.avm:2:10
+//   |
+// 2 |     jump @nowhere;
+//   |          ^^^^^^^^
+//   = note: ...
+
+use std::{fmt::Write, ops::Range};
+
+use crate::diagnostic::{Diagnostic, Severity};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{code}{text}{RESET}")
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+// The line of source a span starts on
+struct Location<'a> {
+    line_number: usize,
+    column: usize,
+    line: &'a str,
+    // Columns on the line to underline
+    underline: Range<usize>,
+}
+
+fn locate<'a>(source: &'a str, span: &Range<usize>) -> Location<'a> {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+
+    // Spans covering several lines only underline the first
+    let end = span.end.clamp(start, line_start + line.len());
+    let column = source[line_start..start].chars().count();
+    let width = source[start..end].chars().count().max(1);
+
+    Location {
+        line_number: source[..line_start].matches('\n').count() + 1,
+        column: column + 1,
+        line,
+        underline: column..column + width,
+    }
+}
+
+/// Render a diagnostic as human readable text.
+///
+/// `source` looks up the contents of a file by name; files it cannot find are reported by
+/// location only. ANSI colours are emitted when `color` is set.
+pub fn render(
+    diagnostic: &Diagnostic,
+    source: impl Fn(&str) -> Option<String>,
+    color: bool,
+) -> String {
+    let style = Style { color };
+    let mut out = String::new();
+
+    let severity_color = match diagnostic.severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+    };
+
+    let primary_source = source(&diagnostic.file);
+    let primary = primary_source
+        .as_deref()
+        .map(|text| locate(text, &diagnostic.span));
+
+    let label_sources: Vec<Option<String>> = diagnostic
+        .labels
+        .iter()
+        .map(|label| source(&label.file))
+        .collect();
+    let labels: Vec<Option<Location>> = diagnostic
+        .labels
+        .iter()
+        .zip(&label_sources)
+        .map(|(label, text)| text.as_deref().map(|text| locate(text, &label.span)))
+        .collect();
+
+    // Wide enough for the largest line number we print
+    let gutter = primary
+        .iter()
+        .chain(labels.iter().flatten())
+        .map(|location| location.line_number.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let pad = " ".repeat(gutter);
+    let bar = style.paint(BLUE, "|");
+
+    let _ = writeln!(
+        out,
+        "{}{}",
+        style.paint(severity_color, &diagnostic.severity.to_string()),
+        style.paint(BOLD, &format!(": {}", diagnostic.message))
+    );
+
+    let snippet = |out: &mut String,
+                   arrow: &str,
+                   file: &str,
+                   location: Option<&Location>,
+                   marker: char,
+                   marker_color: &str,
+                   message: &str| {
+        let Some(location) = location else {
+            let _ = writeln!(out, "{pad}{} {file}", style.paint(BLUE, arrow));
+            if !message.is_empty() {
+                let _ = writeln!(out, "{pad} {} {message}", style.paint(BLUE, "="));
+            }
+            return;
+        };
+
+        let _ = writeln!(
+            out,
+            "{pad}{} {file}:{}:{}",
+            style.paint(BLUE, arrow),
+            location.line_number,
+            location.column
+        );
+        let _ = writeln!(out, "{pad} {bar}");
+        let _ = writeln!(
+            out,
+            "{} {bar} {}",
+            style.paint(BLUE, &format!("{:>gutter$}", location.line_number)),
+            location.line
+        );
+
+        let underline = marker.to_string().repeat(location.underline.len());
+        let mut annotation = format!(
+            "{}{}",
+            " ".repeat(location.underline.start),
+            style.paint(marker_color, &underline)
+        );
+        if !message.is_empty() {
+            annotation.push(' ');
+            annotation.push_str(&style.paint(marker_color, message));
+        }
+        let _ = writeln!(out, "{pad} {bar} {annotation}");
+    };
+
+    snippet(
+        &mut out,
+        "-->",
+        &diagnostic.file,
+        primary.as_ref(),
+        '^',
+        severity_color,
+        "",
+    );
+
+    for (label, location) in diagnostic.labels.iter().zip(&labels) {
+        snippet(
+            &mut out,
+            ":::",
+            &label.file,
+            location.as_ref(),
+            '-',
+            BLUE,
+            &label.message,
+        );
+    }
+
+    for note in &diagnostic.notes {
+        let _ = writeln!(out, "{pad} {} {note}", style.paint(BLUE, "= note:"));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Label;
+
+    #[test]
+    fn renders_source_line_with_labels_and_notes() {
+        let source = "add 1 2 3;\n.macro m {\n    jump @nowhere;\n};\n$m;\n";
+        let mut diagnostic =
+            Diagnostic::error("main.avm", 31..39, "label `nowhere` is not defined")
+                .with_note("labels are case sensitive");
+        diagnostic.labels.push(Label {
+            file: "main.avm".to_owned(),
+            span: 44..46,
+            message: "in this macro invocation".to_owned(),
+        });
+
+        let rendered = render(&diagnostic, |_| Some(source.to_owned()), false);
+        let expected = "\
+error: label `nowhere` is not defined
+ --> main.avm:3:10
+  |
+3 |     jump @nowhere;
+  |          ^^^^^^^^
+ ::: main.avm:5:1
+  |
+5 | $m;
+  | -- in this macro invocation
+  = note: labels are case sensitive
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn renders_location_only_without_source() {
+        let diagnostic = Diagnostic::error("gone.avm", 0..0, "could not read `gone.avm`");
+
+        let rendered = render(&diagnostic, |_| None, false);
+        assert_eq!(
+            rendered,
+            "error: could not read `gone.avm`\n --> gone.avm\n"
+        );
+    }
+
+    #[test]
+    fn colours_are_only_emitted_when_requested() {
+        let diagnostic = Diagnostic::error("main.avm", 0..3, "oops");
+
+        let plain = render(&diagnostic, |_| Some("add;".to_owned()), false);
+        let coloured = render(&diagnostic, |_| Some("add;".to_owned()), true);
+        assert!(!plain.contains('\x1b'));
+        assert!(coloured.contains(RED));
+    }
+}
@@ -0,0 +1,182 @@
+// Map compiled instructions back to the source they were written in
+//
+// Every instruction keeps the span of the statement it was compiled from, along with each macro
+// invocation it was expanded through. Those spans are turned into lines and columns here, both for
+// the debugger and for the debug info written alongside bytecode.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::Path,
+    rc::Rc,
+};
+
+use serde::Serialize;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::loader::FsLoader;
+use crate::{compiler::Assembly, loader::FileLoader, span::Span};
+
+/// A position within a source file, with lines and columns counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Where each instruction of a program was written, for attributing failures back to the source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DebugInfo {
+    pub instructions: Vec<InstructionInfo>,
+    /// The index of the instruction each label refers to. Labels local to a macro are suffixed
+    /// with `#` and the number of the expansion they belong to, e.g. `%loop#3`.
+    pub labels: BTreeMap<String, u64>,
+}
+
+/// The source of a single instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstructionInfo {
+    pub index: usize,
+    /// The byte offset of the instruction within the bytecode
+    pub offset: usize,
+    #[serde(flatten)]
+    pub location: SourceLocation,
+    /// Each macro invocation the instruction was expanded through, innermost first
+    pub expansions: Vec<SourceLocation>,
+}
+
+impl DebugInfo {
+    pub(crate) fn new(assembly: &Assembly, sources: &mut Sources) -> Self {
+        let instructions = assembly
+            .spans
+            .iter()
+            .zip(&assembly.offsets)
+            .enumerate()
+            .map(|(index, (span, &offset))| InstructionInfo {
+                index,
+                offset,
+                location: sources.locate(span),
+                expansions: span
+                    .expansions()
+                    .map(|invocation| sources.locate(invocation))
+                    .collect(),
+            })
+            .collect();
+
+        DebugInfo {
+            instructions,
+            labels: assembly
+                .labels
+                .iter()
+                .map(|(label, &pc)| (label.clone(), pc))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("debug info is always serializable")
+    }
+}
+
+// The contents of every source file, read the first time one of its spans is located. Files that
+// cannot be read are treated as empty
+pub(crate) struct Sources {
+    files: HashMap<Rc<str>, String>,
+    loader: Rc<dyn FileLoader>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for Sources {
+    fn default() -> Self {
+        Sources::new(Rc::new(FsLoader))
+    }
+}
+
+impl Sources {
+    pub fn new(loader: Rc<dyn FileLoader>) -> Self {
+        Sources {
+            files: HashMap::new(),
+            loader,
+        }
+    }
+
+    pub fn locate(&mut self, span: &Span) -> SourceLocation {
+        let source = self.source(&span.file);
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+
+        SourceLocation {
+            file: span.file.to_string(),
+            line: source[..line_start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+        }
+    }
+
+    // Use the given contents for a file, rather than reading it
+    pub fn insert(&mut self, file: &str, contents: &str) {
+        self.files.insert(file.into(), contents.to_owned());
+    }
+
+    // The text of the line a location is on, shown by the debugger
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn line(&self, location: &SourceLocation) -> Option<&str> {
+        self.files
+            .get(location.file.as_str())?
+            .lines()
+            .nth(location.line - 1)
+    }
+
+    fn source(&mut self, file: &Rc<str>) -> &str {
+        let loader = &self.loader;
+        self.files
+            .entry(file.clone())
+            .or_insert_with(|| loader.read(Path::new(&**file)).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{compile_file_with_debug_info, CompileOptions, OutputFormat};
+
+    #[test]
+    fn instructions_map_back_to_their_source() {
+        let (bytecode, debug_info) = compile_file_with_debug_info(
+            &"./test_programs/debug.avm".to_owned(),
+            &CompileOptions::default(),
+            OutputFormat::Binary,
+        )
+        .unwrap();
+        assert_eq!(debug_info.instructions.len(), 9);
+
+        // Offsets point at the start of each instruction within the bytecode
+        let last = &debug_info.instructions[8];
+        assert_eq!(&bytecode[last.offset..], [0x23, 0x00]);
+
+        let json: serde_json::Value = serde_json::from_str(&debug_info.to_json()).unwrap();
+        assert_eq!(
+            json["instructions"][7],
+            serde_json::json!({
+                "index": 7,
+                "offset": 125,
+                "file": "./test_programs/debug.avm",
+                "line": 3,
+                "column": 5,
+                "expansions": [
+                    { "file": "./test_programs/debug.avm", "line": 8, "column": 5 },
+                    { "file": "./test_programs/debug.avm", "line": 21, "column": 5 },
+                ],
+            })
+        );
+        assert_eq!(
+            json["labels"],
+            serde_json::json!({ "main": 0, "double": 6 })
+        );
+    }
+}
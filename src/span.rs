@@ -0,0 +1,54 @@
+use std::{ops::Range, rc::Rc};
+
+/// A byte range within a source file.
+///
+/// Nodes produced by expanding a macro remember the invocation they came from, so that
+/// errors inside a macro body can point at both the definition and every call site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub start: usize,
+    pub end: usize,
+    pub expansion: Option<Rc<Span>>,
+}
+
+impl Span {
+    pub fn new(file: &Rc<str>, start: usize, end: usize) -> Self {
+        Span {
+            file: file.clone(),
+            start,
+            end,
+            expansion: None,
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    // The same span, as seen from within an expansion of the given macro invocation
+    pub fn expanded_from(&self, invocation: &Rc<Span>) -> Span {
+        Span {
+            expansion: Some(invocation.clone()),
+            ..self.clone()
+        }
+    }
+
+    // Every macro invocation this span was expanded through, innermost first
+    pub fn expansions(&self) -> impl Iterator<Item = &Span> {
+        std::iter::successors(self.expansion.as_deref(), |span| span.expansion.as_deref())
+    }
+}
+
+/// An AST node along with where it was written.
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
@@ -0,0 +1,210 @@
+// Bindings for assembling and disassembling programs in the browser
+//
+// Enabled with the `wasm` feature. Files are only ever read from the map passed in, never from
+// disk. The exported `assemble` and `disassemble` are thin layers over `assemble_files` and
+// `disassemble_bytecode`, which are plain Rust so that they can be tested on any target.
+
+use std::{collections::BTreeMap, path::Path, rc::Rc};
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    assembler::Assembler,
+    compiler::{OutputFormat, INPUT},
+    diagnostic::Diagnostic,
+    disassembler::{disassemble_bytes, read_bytecode},
+    isa::Isa,
+    loader::{FileLoader, MemoryLoader},
+    render::render,
+    source_map::Sources,
+    span::Span,
+};
+
+/// The outcome of assembling a program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Assembled {
+    /// The bytecode as uppercase hex, missing if the program has errors
+    pub bytecode: Option<String>,
+    pub diagnostics: Vec<WasmDiagnostic>,
+}
+
+/// A diagnostic, along with where it points in the source and how it reads when rendered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WasmDiagnostic {
+    /// `error` or `warning`
+    pub severity: String,
+    pub message: String,
+    pub file: String,
+    /// The byte range the diagnostic points at
+    pub start: usize,
+    pub end: usize,
+    /// Counted from 1, missing if the file is not known
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The diagnostic as the command line prints it, with the source line underlined
+    pub rendered: String,
+}
+
+/// Assemble `source`, reading any file it includes from `files`, which maps paths to their
+/// contents. Diagnostics in `source` itself refer to it as `<input>`.
+pub fn assemble_files(source: &str, files: BTreeMap<String, String>) -> Assembled {
+    let loader: MemoryLoader = files.into_iter().collect();
+    let result = Assembler::new()
+        .loader(loader.clone())
+        .format(OutputFormat::Hex)
+        .assemble(source);
+
+    // Diagnostics can point into the source or into any of the files
+    let contents = |file: &str| match file {
+        INPUT => Some(source.to_owned()),
+        _ => loader.read(Path::new(file)).ok(),
+    };
+    let mut sources = Sources::new(Rc::new(loader.clone()));
+    sources.insert(INPUT, source);
+    let (bytecode, diagnostics) = match result {
+        Ok(program) => (
+            Some(String::from_utf8_lossy(&program.bytes).into_owned()),
+            program.warnings,
+        ),
+        Err(diagnostics) => (None, diagnostics),
+    };
+
+    Assembled {
+        bytecode,
+        diagnostics: diagnostics
+            .iter()
+            .map(|diagnostic| WasmDiagnostic::new(diagnostic, &contents, &mut sources))
+            .collect(),
+    }
+}
+
+/// Disassemble `bytecode`, written out in any of the text output formats, for the named instruction
+/// set, or the default one if none is given.
+pub fn disassemble_bytecode(bytecode: &str, isa: Option<&str>) -> Result<String, String> {
+    let isa = isa.map_or(Ok(Isa::default()), str::parse)?;
+    let bytes = read_bytecode(bytecode.as_bytes(), None).map_err(|error| error.to_string())?;
+    disassemble_bytes(&bytes, isa).map_err(|error| error.to_string())
+}
+
+impl WasmDiagnostic {
+    fn new(
+        diagnostic: &Diagnostic,
+        contents: &impl Fn(&str) -> Option<String>,
+        sources: &mut Sources,
+    ) -> Self {
+        let position = contents(&diagnostic.file).map(|_| {
+            let file = diagnostic.file.as_str().into();
+            let location = sources.locate(&Span::new(
+                &file,
+                diagnostic.span.start,
+                diagnostic.span.end,
+            ));
+            (location.line, location.column)
+        });
+        WasmDiagnostic {
+            severity: diagnostic.severity.to_string(),
+            message: diagnostic.message.clone(),
+            file: diagnostic.file.clone(),
+            start: diagnostic.span.start,
+            end: diagnostic.span.end,
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            rendered: render(diagnostic, contents, false),
+        }
+    }
+}
+
+/// Assemble `source`, reading any file it includes from `files`, an object mapping paths to their
+/// contents. Returns `{ bytecode, diagnostics }`, with `bytecode` null if the program has errors.
+#[wasm_bindgen]
+pub fn assemble(source: &str, files: JsValue) -> Result<JsValue, JsValue> {
+    let files = if files.is_undefined() || files.is_null() {
+        BTreeMap::new()
+    } else {
+        serde_wasm_bindgen::from_value(files)?
+    };
+
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    Ok(assemble_files(source, files).serialize(&serializer)?)
+}
+
+/// Disassemble `bytecode`, as hex or base64, for the named instruction set, or `v1` if it is not
+/// given. Throws if the bytecode cannot be decoded.
+#[wasm_bindgen]
+pub fn disassemble(bytecode: &str, isa: Option<String>) -> Result<String, JsValue> {
+    disassemble_bytecode(bytecode, isa.as_deref()).map_err(|error| JsValue::from_str(&error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_are_assembled_from_the_given_files() {
+        let files = BTreeMap::from([(
+            "lib.avm".to_owned(),
+            ".macro double(a) {\n    add $a $a $a;\n};".to_owned(),
+        )]);
+
+        let assembled = assemble_files(".include \"lib.avm\";\n$double(1);", files);
+        assert_eq!(
+            assembled,
+            Assembled {
+                bytecode: Some("0000000000000000000100000000000000010000000000000001".to_owned()),
+                diagnostics: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn diagnostics_point_into_the_source_and_files() {
+        let files = BTreeMap::from([(
+            "lib.avm".to_owned(),
+            ".macro broken {\n    jump @nowhere;\n};".to_owned(),
+        )]);
+
+        let assembled = assemble_files(".include \"lib.avm\";\n$broken;", files);
+        assert_eq!(assembled.bytecode, None);
+
+        let diagnostic = &assembled.diagnostics[0];
+        assert_eq!(diagnostic.severity, "error");
+        assert_eq!(diagnostic.message, "label `nowhere` is not defined");
+        assert_eq!(diagnostic.file, "lib.avm");
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(2), Some(10)));
+        assert!(diagnostic.rendered.contains("2 |     jump @nowhere;"));
+        assert!(diagnostic.rendered.contains("in this macro invocation"));
+
+        let assembled = assemble_files("add 0 0 0;\njump @nowhere;", BTreeMap::new());
+        let diagnostic = &assembled.diagnostics[0];
+        assert_eq!(
+            (diagnostic.file.as_str(), diagnostic.line),
+            (INPUT, Some(2))
+        );
+    }
+
+    #[test]
+    fn bytecode_is_disassembled() {
+        let bytecode = assemble_files("start:\n    add 1 2 3;\n    jump @start;", BTreeMap::new())
+            .bytecode
+            .unwrap();
+        let expected = "label_0:\n    add 1 2 3;\n    jump @label_0;\n";
+        assert_eq!(
+            disassemble_bytecode(&bytecode, None),
+            Ok(expected.to_owned())
+        );
+        assert_eq!(
+            disassemble_bytecode(&bytecode, Some("v1")),
+            Ok(expected.to_owned())
+        );
+
+        assert_eq!(
+            disassemble_bytecode(&bytecode, Some("v0")),
+            Err("unknown instruction set `v0`, expected one of v1".to_owned())
+        );
+        assert_eq!(
+            disassemble_bytecode("ff00", None),
+            Err("unknown opcode `0xff` in the v1 instruction set (at byte 0)".to_owned())
+        );
+    }
+}